@@ -0,0 +1,371 @@
+//! SQLite storage of the discovered endpoints and of the history of every scan.
+//!
+//! Endpoints, services, records and fingerprints are updated as they change and keep the
+//! first and last time they've been seen, records that expired are kept with their expiry
+//! time. Every event is appended to the sightings of the scan it happened in.
+use std::net::IpAddr;
+
+use rusqlite::{params, Connection, OpenFlags, Transaction};
+
+use crate::mdns;
+use crate::mdns::discovery::MappedEndpoints;
+use crate::mdns::event::{Event, Timestamped};
+use crate::mdns::Endpoint;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS scans (
+    id INTEGER PRIMARY KEY,
+    started_at INTEGER NOT NULL,
+    finished_at INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS endpoints (
+    address TEXT PRIMARY KEY,
+    name TEXT,
+    interface TEXT,
+    local INTEGER NOT NULL,
+    online INTEGER NOT NULL,
+    first_seen INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    -- the endpoint as JSON, used to restore it
+    state TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS services (
+    id INTEGER PRIMARY KEY,
+    address TEXT NOT NULL REFERENCES endpoints(address),
+    name TEXT NOT NULL,
+    description TEXT,
+    first_seen INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    UNIQUE(address, name)
+);
+
+CREATE TABLE IF NOT EXISTS records (
+    id INTEGER PRIMARY KEY,
+    service_id INTEGER NOT NULL REFERENCES services(id),
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    record_type TEXT NOT NULL,
+    class TEXT NOT NULL,
+    ttl INTEGER NOT NULL,
+    cache_flush INTEGER NOT NULL,
+    first_seen INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL,
+    UNIQUE(service_id, key, value)
+);
+
+CREATE TABLE IF NOT EXISTS fingerprints (
+    id INTEGER PRIMARY KEY,
+    address TEXT NOT NULL REFERENCES endpoints(address),
+    vendor TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    confidence REAL NOT NULL,
+    model TEXT,
+    os TEXT,
+    os_version TEXT,
+    firmware TEXT,
+    friendly_name TEXT,
+    first_seen INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    UNIQUE(address, vendor, kind)
+);
+
+CREATE TABLE IF NOT EXISTS sightings (
+    id INTEGER PRIMARY KEY,
+    scan_id INTEGER NOT NULL REFERENCES scans(id),
+    address TEXT NOT NULL,
+    event TEXT NOT NULL,
+    service TEXT,
+    key TEXT,
+    value TEXT,
+    seen_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS sightings_by_address ON sightings(address, seen_at);
+";
+
+/// A SQLite database where the results of the scans are accumulated.
+pub struct Database {
+    conn: Connection,
+    // the scan running right now, if started
+    scan: Option<i64>,
+}
+
+fn to_string(e: rusqlite::Error) -> String {
+    e.to_string()
+}
+
+impl Database {
+    /// Open the database, creating it if needed.
+    pub fn open(path: &str) -> Result<Self, String> {
+        let conn = Connection::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        // a write for every change is fine as long as it doesn't sync every time
+        conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")
+            .map_err(to_string)?;
+        conn.execute_batch(SCHEMA).map_err(to_string)?;
+
+        Ok(Self { conn, scan: None })
+    }
+
+    /// Start a new scan, the changes stored from now on are part of it.
+    pub fn start_scan(&mut self) -> Result<(), String> {
+        self.conn
+            .execute(
+                "INSERT INTO scans (started_at) VALUES (?1)",
+                params![mdns::now()],
+            )
+            .map_err(to_string)?;
+        self.scan = Some(self.conn.last_insert_rowid());
+
+        Ok(())
+    }
+
+    fn scan(&self) -> Result<i64, String> {
+        self.scan
+            .ok_or_else(|| "no scan has been started".to_string())
+    }
+
+    /// The endpoints as they were last stored, by any scan.
+    pub fn endpoints(&self) -> Result<MappedEndpoints, String> {
+        load(&self.conn)
+    }
+
+    /// Store the endpoint that changed with this event and add the event to the sightings
+    /// of the scan, at the time it happened.
+    pub fn update(&mut self, event: &Timestamped, endpoint: &Endpoint) -> Result<(), String> {
+        let scan = self.scan()?;
+        let tx = self.conn.transaction().map_err(to_string)?;
+        save(&tx, endpoint)?;
+        add_sighting(&tx, scan, event)?;
+        tx.commit().map_err(to_string)
+    }
+
+    /// Store the final state of the endpoints and mark the scan as finished.
+    pub fn finish(&mut self, endpoints: &MappedEndpoints) -> Result<(), String> {
+        let scan = self.scan()?;
+        let tx = self.conn.transaction().map_err(to_string)?;
+        for endpoint in endpoints.values() {
+            save(&tx, endpoint)?;
+        }
+        tx.execute(
+            "UPDATE scans SET finished_at = ?1 WHERE id = ?2",
+            params![mdns::now(), scan],
+        )
+        .map_err(to_string)?;
+        tx.commit().map_err(to_string)
+    }
+}
+
+/// Read the endpoints stored in the database at this path, without starting a scan.
+pub fn read(path: &str) -> Result<MappedEndpoints, String> {
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("{}: {}", path, e))?;
+    load(&conn)
+}
+
+fn load(conn: &Connection) -> Result<MappedEndpoints, String> {
+    let mut statement = conn
+        .prepare("SELECT address, state FROM endpoints")
+        .map_err(to_string)?;
+    let rows = statement
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })
+        .map_err(to_string)?;
+
+    let mut endpoints = MappedEndpoints::new();
+    for row in rows {
+        let (address, state) = row.map_err(to_string)?;
+        let endpoint: Endpoint = serde_json::from_str(&state)
+            .map_err(|e| format!("could not restore {}: {}", address, e))?;
+        endpoints.insert(endpoint.address, endpoint);
+    }
+
+    Ok(endpoints)
+}
+
+fn save(tx: &Transaction, endpoint: &Endpoint) -> Result<(), String> {
+    let address = endpoint.address.to_string();
+    let state = serde_json::to_string(endpoint).map_err(|e| e.to_string())?;
+
+    tx.execute(
+        "INSERT INTO endpoints (address, name, interface, local, online, first_seen, last_seen, state)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+         ON CONFLICT(address) DO UPDATE SET
+            name = excluded.name,
+            interface = excluded.interface,
+            local = excluded.local,
+            online = excluded.online,
+            last_seen = excluded.last_seen,
+            state = excluded.state",
+        params![
+            address,
+            endpoint.name,
+            endpoint.interface,
+            endpoint.local,
+            endpoint.online,
+            endpoint.first_seen,
+            endpoint.last_seen,
+            state
+        ],
+    )
+    .map_err(to_string)?;
+
+    for service in endpoint.services.values() {
+        tx.execute(
+            "INSERT INTO services (address, name, description, first_seen, last_seen)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT(address, name) DO UPDATE SET
+                description = excluded.description,
+                last_seen = excluded.last_seen",
+            params![
+                address,
+                service.name,
+                service.description,
+                service.first_seen,
+                service.last_seen
+            ],
+        )
+        .map_err(to_string)?;
+
+        let service_id: i64 = tx
+            .query_row(
+                "SELECT id FROM services WHERE address = ?1 AND name = ?2",
+                params![address, service.name],
+                |row| row.get(0),
+            )
+            .map_err(to_string)?;
+
+        for (key, values) in &service.properties.0 {
+            for value in values {
+                tx.execute(
+                    "INSERT INTO records (service_id, key, value, record_type, class, ttl,
+                        cache_flush, first_seen, last_seen, expires_at)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                     ON CONFLICT(service_id, key, value) DO UPDATE SET
+                        ttl = excluded.ttl,
+                        cache_flush = excluded.cache_flush,
+                        last_seen = excluded.last_seen,
+                        expires_at = excluded.expires_at",
+                    params![
+                        service_id,
+                        key,
+                        value.value,
+                        value.record_type,
+                        value.class,
+                        value.ttl,
+                        value.cache_flush,
+                        value.first_seen,
+                        value.last_seen,
+                        value.expires_at
+                    ],
+                )
+                .map_err(to_string)?;
+            }
+        }
+    }
+
+    if let Some(fingerprint) = &endpoint.fingerprint {
+        tx.execute(
+            "INSERT INTO fingerprints (address, vendor, kind, confidence, model, os, os_version,
+                firmware, friendly_name, first_seen, last_seen)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?10)
+             ON CONFLICT(address, vendor, kind) DO UPDATE SET
+                confidence = excluded.confidence,
+                model = excluded.model,
+                os = excluded.os,
+                os_version = excluded.os_version,
+                firmware = excluded.firmware,
+                friendly_name = excluded.friendly_name,
+                last_seen = excluded.last_seen",
+            params![
+                address,
+                fingerprint.vendor,
+                fingerprint.kind,
+                fingerprint.confidence,
+                fingerprint.model,
+                fingerprint.os,
+                fingerprint.os_version,
+                fingerprint.firmware,
+                fingerprint.friendly_name,
+                endpoint.last_seen
+            ],
+        )
+        .map_err(to_string)?;
+    }
+
+    Ok(())
+}
+
+// (address, event, service, key, value)
+type Sighting = (
+    IpAddr,
+    &'static str,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+fn sighting(event: &Event) -> Sighting {
+    match event {
+        Event::EndpointDiscovered { endpoint } => {
+            (endpoint.address, "discovered", None, None, None)
+        }
+        Event::ServiceAdded { address, service } => (
+            *address,
+            "service",
+            Some(service.name.to_owned()),
+            None,
+            None,
+        ),
+        Event::PropertyChanged {
+            address,
+            service,
+            key,
+            value,
+        } => (
+            *address,
+            "property",
+            Some(service.to_owned()),
+            Some(key.to_owned()),
+            Some(value.to_owned()),
+        ),
+        Event::RecordExpired {
+            address,
+            service,
+            key,
+            value,
+        } => (
+            *address,
+            "expired",
+            Some(service.to_owned()),
+            Some(key.to_owned()),
+            Some(value.to_owned()),
+        ),
+        Event::EndpointGone { address } => (*address, "gone", None, None, None),
+        Event::QueryObserved { source, questions } => {
+            (*source, "query", None, None, Some(questions.join(", ")))
+        }
+    }
+}
+
+fn add_sighting(tx: &Transaction, scan: i64, event: &Timestamped) -> Result<(), String> {
+    let (address, name, service, key, value) = sighting(&event.event);
+    tx.execute(
+        "INSERT INTO sightings (scan_id, address, event, service, key, value, seen_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        params![
+            scan,
+            address.to_string(),
+            name,
+            service,
+            key,
+            value,
+            event.timestamp
+        ],
+    )
+    .map_err(to_string)?;
+    Ok(())
+}
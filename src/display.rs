@@ -1,18 +1,103 @@
+use std::io::{self, Write};
+
 use colored::Colorize;
+use itertools::Itertools;
+
+use spycast::{Candidate, Endpoint, Fingerprint, SharedEndpoints, Timestamped, TxtRecord};
+
+// writes the event and its time as a single line of JSON
+pub fn ndjson(out: &mut impl Write, event: &Timestamped) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, event)?;
+    writeln!(out)?;
+    out.flush()
+}
 
-use crate::mdns::Endpoint;
+pub fn endpoints(endpoints: &SharedEndpoints) {
+    if let Ok(guard) = endpoints.lock() {
+        clearscreen::clear().unwrap();
+        for entry in guard.values() {
+            endpoint(entry);
+        }
+    }
+}
+
+fn txt(record: &TxtRecord) {
+    for (key, value) in record.iter().sorted_by_key(|(key, _)| *key) {
+        println!("    {} = {}", key, value.to_string().bright_blue());
+    }
+}
+
+fn fingerprint_line(fingerprint: &Fingerprint) {
+    let mut parts = vec![];
+    for part in [&fingerprint.vendor, &fingerprint.kind] {
+        if !part.is_empty() {
+            parts.push(part.to_owned());
+        }
+    }
+    if let Some(model) = &fingerprint.model {
+        parts.push(model.to_owned());
+    }
+    match (&fingerprint.os, &fingerprint.os_version) {
+        (Some(os), Some(version)) => parts.push(format!("{} {}", os, version)),
+        (Some(os), None) => parts.push(os.to_owned()),
+        (None, Some(version)) => parts.push(format!("os {}", version)),
+        (None, None) => {}
+    }
+    if let Some(firmware) = &fingerprint.firmware {
+        parts.push(format!("firmware {}", firmware));
+    }
+    if let Some(name) = &fingerprint.friendly_name {
+        parts.push(format!("\"{}\"", name));
+    }
+    if fingerprint.confidence > 0.0 {
+        parts.push(format!("({:.0}%)", fingerprint.confidence * 100.0));
+    }
+    println!("  {}", parts.join(" ").magenta());
+}
+
+fn candidates(candidates: &[Candidate]) {
+    // the first one is the fingerprint
+    for candidate in candidates.iter().skip(1) {
+        println!(
+            "  {} {}/{} ({:.0}%): {}",
+            "candidate".bright_black(),
+            candidate.vendor,
+            candidate.kind,
+            candidate.confidence * 100.0,
+            candidate.evidence.join(", ").bright_black()
+        );
+    }
+}
 
 pub fn endpoint(endpoint: &Endpoint) {
     if endpoint.name.is_none() {
-        print!("<{}>\r\n", endpoint.address);
+        print!("<{}>", endpoint.address);
     } else {
         print!(
-            "<{}> ({})\r\n",
+            "<{}> ({})",
             endpoint.address,
             endpoint.name.as_ref().unwrap()
         );
     }
 
+    if let Some(interface) = &endpoint.interface {
+        print!(" on {}", interface.bright_black());
+    }
+
+    if !endpoint.online {
+        print!(" {}", "offline".red());
+    }
+
+    if endpoint.stale {
+        print!(" {}", "stale".yellow());
+    }
+    print!("\r\n");
+
+    if let Some(fingerprint) = &endpoint.fingerprint {
+        fingerprint_line(fingerprint);
+    }
+    candidates(&endpoint.candidates);
+
     for service in endpoint.services.values() {
         if let Some(desc) = &service.description {
             print!("  {} {}\r\n", service.name.green(), desc.yellow());
@@ -21,14 +106,70 @@ pub fn endpoint(endpoint: &Endpoint) {
         }
 
         for (key, values) in &service.properties.0 {
+            // shown parsed
+            if (key == "text" && !service.txt.is_empty())
+                || (key == "server" && !service.srv.is_empty())
+            {
+                continue;
+            }
+
             for value in values {
                 if key == "server" {
-                    println!("    server: {}", value.bright_red());
+                    println!("    server: {}", value.value.bright_red());
                 } else {
-                    println!("    {}: {}", key, value.bright_blue());
+                    println!("    {}: {}", key, value.value.bright_blue());
                 }
             }
         }
+
+        for srv in &service.srv {
+            print!(
+                "    server: {} port {} (priority {}, weight {})",
+                srv.target.bright_red(),
+                srv.port,
+                srv.priority,
+                srv.weight
+            );
+            if !srv.addresses.is_empty() {
+                print!(" at {}", srv.addresses.iter().join(", ").bright_blue());
+            }
+            print!("\r\n");
+        }
+
+        txt(&service.txt);
+    }
+
+    for instance in endpoint.instances.values() {
+        print!("  {} ({})", instance.name.cyan(), instance.service_type);
+        if let (Some(host), Some(port)) = (&instance.host, instance.port) {
+            print!(" on {}:{}", host.bright_red(), port);
+        }
+        if !instance.addresses.is_empty() {
+            print!(" {}", instance.addresses.iter().join(", ").bright_blue());
+        }
+        print!("\r\n");
+
+        txt(&instance.txt);
+    }
+
+    for interest in &endpoint.interests {
+        print!(
+            "  {} {} {}",
+            "asks".bright_black(),
+            interest.name.yellow(),
+            interest.query_type
+        );
+        if interest.unicast_response {
+            print!(" (QU)");
+        }
+        if interest.count > 1 {
+            print!(" x{}", interest.count);
+        }
+        print!("\r\n");
+
+        for answer in &interest.known_answers {
+            println!("    knows {}", answer.bright_blue());
+        }
     }
     println!();
 }
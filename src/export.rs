@@ -0,0 +1,268 @@
+//! Flat CSV inventory of the endpoints, with one row per service instance of each endpoint.
+use std::io::Write;
+
+use crate::mdns::discovery::MappedEndpoints;
+use crate::mdns::{Endpoint, Instance};
+
+/// The columns exported when none are selected.
+pub const DEFAULT_COLUMNS: &str =
+    "address,hostname,vendor,kind,model,service,description,instance,port,txt";
+
+/// A column of the export, `txt.<key>` selects the value of a single TXT key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Column {
+    Address,
+    Hostname,
+    Interface,
+    Online,
+    Stale,
+    FirstSeen,
+    LastSeen,
+    Vendor,
+    Kind,
+    Model,
+    Os,
+    OsVersion,
+    Firmware,
+    FriendlyName,
+    Service,
+    Description,
+    Instance,
+    Host,
+    Port,
+    Addresses,
+    Txt,
+    TxtKey(String),
+}
+
+const NAMES: &[(&str, Column)] = &[
+    ("address", Column::Address),
+    ("hostname", Column::Hostname),
+    ("interface", Column::Interface),
+    ("online", Column::Online),
+    ("stale", Column::Stale),
+    ("first_seen", Column::FirstSeen),
+    ("last_seen", Column::LastSeen),
+    ("vendor", Column::Vendor),
+    ("kind", Column::Kind),
+    ("model", Column::Model),
+    ("os", Column::Os),
+    ("os_version", Column::OsVersion),
+    ("firmware", Column::Firmware),
+    ("friendly_name", Column::FriendlyName),
+    ("service", Column::Service),
+    ("description", Column::Description),
+    ("instance", Column::Instance),
+    ("host", Column::Host),
+    ("port", Column::Port),
+    ("addresses", Column::Addresses),
+    ("txt", Column::Txt),
+];
+
+impl Column {
+    pub fn parse(name: &str) -> Result<Column, String> {
+        let name = name.trim();
+        if let Some(key) = name.strip_prefix("txt.") {
+            if key.is_empty() {
+                return Err("txt. must be followed by a key".to_owned());
+            }
+            // keys are stored lowercase
+            return Ok(Column::TxtKey(key.to_ascii_lowercase()));
+        }
+
+        NAMES
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, column)| column.clone())
+            .ok_or_else(|| {
+                let known: Vec<&str> = NAMES.iter().map(|(known, _)| *known).collect();
+                format!(
+                    "unknown column '{}', expected one of {} or txt.<key>",
+                    name,
+                    known.join(", ")
+                )
+            })
+    }
+
+    /// The name used in the header.
+    pub fn name(&self) -> String {
+        match self {
+            Column::TxtKey(key) => format!("txt.{}", key),
+            column => NAMES
+                .iter()
+                .find(|(_, known)| known == column)
+                .map(|(name, _)| name.to_string())
+                .unwrap_or_default(),
+        }
+    }
+
+    fn value(&self, endpoint: &Endpoint, instance: Option<&Instance>) -> String {
+        let fingerprint = endpoint.fingerprint.as_ref();
+        let optional = |value: Option<&String>| value.cloned().unwrap_or_default();
+
+        match self {
+            Column::Address => endpoint.address.to_string(),
+            Column::Hostname => optional(endpoint.name.as_ref()),
+            Column::Interface => optional(endpoint.interface.as_ref()),
+            Column::Online => endpoint.online.to_string(),
+            Column::Stale => endpoint.stale.to_string(),
+            Column::FirstSeen => endpoint.first_seen.to_string(),
+            Column::LastSeen => endpoint.last_seen.to_string(),
+            Column::Vendor => optional(fingerprint.map(|f| &f.vendor)),
+            Column::Kind => optional(fingerprint.map(|f| &f.kind)),
+            Column::Model => optional(fingerprint.and_then(|f| f.model.as_ref())),
+            Column::Os => optional(fingerprint.and_then(|f| f.os.as_ref())),
+            Column::OsVersion => optional(fingerprint.and_then(|f| f.os_version.as_ref())),
+            Column::Firmware => optional(fingerprint.and_then(|f| f.firmware.as_ref())),
+            Column::FriendlyName => optional(fingerprint.and_then(|f| f.friendly_name.as_ref())),
+            Column::Service => optional(instance.map(|i| &i.service_type)),
+            // as stored by the scan, either under the service type or the instance name
+            Column::Description => optional(instance.and_then(|i| {
+                [&i.service_type, &i.name]
+                    .iter()
+                    .filter_map(|name| endpoint.services.get(*name))
+                    .find_map(|s| s.description.as_ref())
+            })),
+            Column::Instance => optional(instance.map(|i| &i.name)),
+            Column::Host => optional(instance.and_then(|i| i.host.as_ref())),
+            Column::Port => instance
+                .and_then(|i| i.port)
+                .map(|port| port.to_string())
+                .unwrap_or_default(),
+            Column::Addresses => instance
+                .map(|i| {
+                    let addresses: Vec<String> =
+                        i.addresses.iter().map(|a| a.to_string()).collect();
+                    addresses.join(" ")
+                })
+                .unwrap_or_default(),
+            Column::Txt => instance
+                .map(|i| {
+                    let mut pairs: Vec<String> = i
+                        .txt
+                        .iter()
+                        .map(|(key, value)| format!("{}={}", key, value))
+                        .collect();
+                    pairs.sort();
+                    pairs.join(";")
+                })
+                .unwrap_or_default(),
+            Column::TxtKey(key) => instance
+                .and_then(|i| i.txt.get(key))
+                .map(|value| value.to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Parse a list of column names.
+pub fn columns<S: AsRef<str>>(names: &[S]) -> Result<Vec<Column>, String> {
+    let columns = names
+        .iter()
+        .map(|name| Column::parse(name.as_ref()))
+        .collect::<Result<Vec<Column>, String>>()?;
+
+    if columns.is_empty() {
+        return Err("no columns selected".to_owned());
+    }
+
+    Ok(columns)
+}
+
+// the service type of a service or instance name, as in _ipp._tcp.local, none for host names
+fn service_type(name: &str) -> Option<&str> {
+    let start = if name.starts_with('_') {
+        0
+    } else {
+        name.find("._")? + 1
+    };
+    let service_type = &name[start..];
+
+    service_type
+        .split('.')
+        .any(|label| label == "_tcp" || label == "_udp")
+        .then_some(service_type)
+}
+
+// the instances of the endpoint and, as instances without a name or without a type
+// pointing to them, the services that are not part of any instance yet
+fn entries(endpoint: &Endpoint) -> Vec<Instance> {
+    let mut entries: Vec<Instance> = endpoint.instances.values().cloned().collect();
+
+    for service in endpoint.services.values() {
+        let covered = endpoint
+            .instances
+            .values()
+            .any(|instance| instance.name == service.name || instance.service_type == service.name);
+        if covered {
+            continue;
+        }
+
+        let service_type = match service_type(&service.name) {
+            Some(service_type) => service_type.to_owned(),
+            None => continue,
+        };
+        let srv = service.srv.first();
+        entries.push(Instance {
+            name: if service_type == service.name {
+                String::new()
+            } else {
+                service.name.to_owned()
+            },
+            service_type,
+            host: srv.map(|srv| srv.target.to_owned()),
+            port: srv.map(|srv| srv.port),
+            addresses: srv.map(|srv| srv.addresses.clone()).unwrap_or_default(),
+            txt: service.txt.clone(),
+        });
+    }
+
+    entries.sort_by(|a, b| (&a.service_type, &a.name).cmp(&(&b.service_type, &b.name)));
+    entries
+}
+
+/// One row for every instance and for every service that is not part of an instance,
+/// sorted by address and service type. Endpoints without services get a single row with
+/// empty service columns.
+pub fn rows(endpoints: &MappedEndpoints, columns: &[Column]) -> Vec<Vec<String>> {
+    let mut sorted: Vec<&Endpoint> = endpoints.values().collect();
+    sorted.sort_by_key(|endpoint| endpoint.address);
+
+    let mut rows = vec![];
+    for endpoint in sorted {
+        let entries = entries(endpoint);
+
+        if entries.is_empty() {
+            rows.push(columns.iter().map(|c| c.value(endpoint, None)).collect());
+        }
+
+        for entry in &entries {
+            rows.push(
+                columns
+                    .iter()
+                    .map(|c| c.value(endpoint, Some(entry)))
+                    .collect(),
+            );
+        }
+    }
+
+    rows
+}
+
+/// Write the endpoints as CSV with a header row.
+pub fn write_csv(
+    out: impl Write,
+    endpoints: &MappedEndpoints,
+    columns: &[Column],
+) -> Result<(), String> {
+    let mut writer = csv::Writer::from_writer(out);
+
+    let header: Vec<String> = columns.iter().map(|c| c.name()).collect();
+    writer.write_record(&header).map_err(|e| e.to_string())?;
+
+    for row in rows(endpoints, columns) {
+        writer.write_record(&row).map_err(|e| e.to_string())?;
+    }
+
+    writer.flush().map_err(|e| e.to_string())
+}
@@ -0,0 +1,24 @@
+//! SpyCast mDNS discovery library.
+//!
+//! ```no_run
+//! let mut agent = spycast::Agent::builder().passive(true).build().unwrap();
+//! let events = agent.subscribe();
+//!
+//! std::thread::spawn(move || agent.start());
+//!
+//! for event in events {
+//!     println!("{:?}", event);
+//! }
+//! ```
+pub mod db;
+pub mod export;
+pub mod mdns;
+pub mod snapshot;
+
+pub use db::Database;
+
+pub use mdns::discovery::{Agent, AgentBuilder, MappedEndpoints, SharedEndpoints, StopHandle};
+pub use mdns::event::{Event, Timestamped};
+pub use mdns::transport::{MemoryPeer, MemoryTransport, PcapTransport, Transport, UdpTransport};
+pub use mdns::txt::{TxtRecord, TxtValue};
+pub use mdns::{Candidate, Endpoint, Fingerprint, Instance, Interest, Properties, Service, Srv};
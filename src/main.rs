@@ -3,24 +3,36 @@
     windows_subsystem = "windows"
 )]
 
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-
-use clap::Parser;
+use std::fs::File;
+use std::net::{IpAddr, SocketAddr};
+use std::thread;
+use std::time::Duration;
 
-mod mdns;
+use clap::{ArgGroup, Parser, Subcommand};
 
 #[cfg(not(feature = "ui"))]
 mod display;
 
 #[cfg(feature = "ui")]
 mod ui;
-#[cfg(feature = "ui")]
-use std::thread;
 
-use mdns::discovery::{Agent, MappedEndpoints, SharedEndpoints};
+use spycast::mdns::discovery::MULTICAST_PORT;
+use spycast::{
+    db, export, snapshot, Agent, Database, MappedEndpoints, SharedEndpoints, Timestamped,
+};
+
+#[cfg(not(feature = "ui"))]
+#[derive(clap::ValueEnum, Default, Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    /// Redraw the endpoints on every change.
+    #[default]
+    Text,
+    /// Write every event as one JSON object per line.
+    Ndjson,
+}
 
 #[derive(Parser, Default, Debug, Clone)]
+#[clap(group(ArgGroup::new("storage").args(&["save-path", "db"]).multiple(true)))]
 struct Arguments {
     /// When in active mode send mDNS queries at this interval.
     #[clap(long, default_value_t = 5)]
@@ -34,73 +46,321 @@ struct Arguments {
     /// Save discovered endpoints as JSON files inside this folder.
     #[clap(long)]
     save_path: Option<String>,
+    /// Save the endpoints that changed at most once every this many seconds.
+    #[clap(long, default_value_t = 2)]
+    save_interval: u64,
+    /// Store the endpoints and the history of the scans in this SQLite database.
+    #[clap(long)]
+    db: Option<String>,
+    /// Start from the endpoints stored by the previous scans in the save path or database.
+    #[clap(long, requires = "storage")]
+    resume: bool,
+    /// Only run discovery over IPv4.
+    #[clap(long, conflicts_with = "ipv6-only")]
+    ipv4_only: bool,
+    /// Only run discovery over IPv6.
+    #[clap(long)]
+    ipv6_only: bool,
+    /// Run discovery on this interface, by name or address (can be repeated).
+    #[clap(long = "interface")]
+    interfaces: Vec<String>,
+    /// Stop the scan after this many seconds.
+    #[clap(long)]
+    duration: Option<u64>,
+    /// Stop the scan once nothing new has been seen for this many seconds.
+    #[clap(long)]
+    max_idle: Option<u64>,
+    /// Load extra fingerprinting rules from this JSON file (can be repeated).
+    #[clap(long = "fingerprints")]
+    fingerprints: Vec<String>,
+    /// Load extra service descriptions from this CSV file (can be repeated).
+    #[clap(long = "services")]
+    services: Vec<String>,
+    /// Read mDNS packets from this pcap or pcapng file instead of the network.
+    #[clap(long)]
+    pcap: Option<String>,
+    /// Write every mDNS packet sent or received to this pcapng file.
+    #[clap(long, conflicts_with = "pcap")]
+    write_pcap: Option<String>,
+    /// Query this host directly by unicast, as address or address:port (can be repeated).
+    #[clap(
+        long = "target",
+        value_parser = parse_target,
+        conflicts_with_all = &["passive", "pcap", "interfaces", "ipv4-only", "ipv6-only"]
+    )]
+    targets: Vec<SocketAddr>,
+    /// Ask for unicast responses by setting the QU bit of the questions.
+    #[clap(long, conflicts_with = "passive")]
+    unicast_response: bool,
+    /// How to print the results on stdout.
+    #[cfg(not(feature = "ui"))]
+    #[clap(long, value_enum, default_value_t = Output::Text)]
+    output: Output,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum Command {
+    /// Export the saved endpoints as CSV, one row per service instance of each endpoint.
+    Export(Export),
+}
+
+#[derive(clap::Args, Debug, Clone)]
+#[clap(group(ArgGroup::new("source").args(&["save-path", "db"]).required(true)))]
+struct Export {
+    /// Read the endpoints saved as JSON files inside this folder.
+    #[clap(long)]
+    save_path: Option<String>,
+    /// Read the endpoints stored in this SQLite database.
+    #[clap(long, conflicts_with = "save-path")]
+    db: Option<String>,
+    /// Comma separated columns to export, txt.<key> selects the value of a TXT key.
+    #[clap(long, use_value_delimiter = true, default_value = export::DEFAULT_COLUMNS)]
+    columns: Vec<String>,
+    /// Write to this file instead of stdout.
+    #[clap(long)]
+    output: Option<String>,
+}
+
+// mDNS port if not specified
+fn parse_target(target: &str) -> Result<SocketAddr, String> {
+    if let Ok(address) = target.parse::<SocketAddr>() {
+        return Ok(address);
+    }
+
+    target
+        .parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, MULTICAST_PORT))
+        .map_err(|_| format!("'{}' is not an address or address:port", target))
 }
 
-fn save_to_path(path: &String, endpoints: &MappedEndpoints) {
-    let path = std::path::Path::new(path);
+// saves the endpoints that changed on a separate thread, until the agent stops
+fn start_snapshots(args: &Arguments, agent: &mut Agent) -> Option<thread::JoinHandle<()>> {
+    args.save_path.as_ref().map(|path| {
+        let interval = Duration::from_secs(args.save_interval);
+        let writer = snapshot::Writer::new(path, interval, agent.endpoints());
+        let events = agent.subscribe();
+        thread::spawn(move || writer.run(events))
+    })
+}
+
+// store the endpoint that changed with this event together with its sighting, queriers
+// included since the agent adds them as endpoints before their queries are observed, so
+// an event is only skipped if its endpoint can't be found anymore
+fn store_event(db: &mut Database, endpoints: &SharedEndpoints, event: &Timestamped) {
+    let address = event.event.endpoint_address();
+    // not holding the lock while writing
+    let endpoint = match endpoints.lock() {
+        Ok(guard) => guard.get(&address).cloned(),
+        Err(_) => None,
+    };
+
+    if let Some(endpoint) = endpoint {
+        if let Err(e) = db.update(event, &endpoint) {
+            eprintln!("error updating the database: {}", e);
+        }
+    }
+}
 
-    std::fs::create_dir_all(&path).unwrap();
+fn store_all(db: &mut Database, endpoints: &SharedEndpoints) {
+    if let Ok(guard) = endpoints.lock() {
+        if let Err(e) = db.finish(&guard) {
+            eprintln!("error updating the database: {}", e);
+        }
+    }
+}
+
+fn open_db(args: &Arguments) -> Result<Option<Database>, String> {
+    match &args.db {
+        Some(path) => Database::open(path).map(Some),
+        None => Ok(None),
+    }
+}
 
-    for endpoint in endpoints.values() {
-        let filepath = path.join(format!("{}.json", endpoint.address));
-        let json = serde_json::to_string_pretty(&endpoint).unwrap();
-        std::fs::write(filepath, &json).unwrap();
+// only once the agent has been created, so that a scan that can't start is not recorded
+fn start_scan(db: &mut Option<Database>) -> Result<(), String> {
+    match db {
+        Some(db) => db.start_scan(),
+        None => Ok(()),
+    }
+}
+
+fn create_agent(args: &Arguments, db: Option<&Database>) -> Result<Agent, String> {
+    let mut builder = Agent::builder()
+        .query_interval(args.query_interval)
+        .passive(args.passive)
+        .filter_for(args.address.clone())
+        .ipv4(!args.ipv6_only)
+        .ipv6(!args.ipv4_only)
+        .unicast_response(args.unicast_response);
+
+    for target in &args.targets {
+        builder = builder.target(*target);
+    }
+
+    for interface in &args.interfaces {
+        builder = builder.interface(interface);
+    }
+
+    for path in &args.fingerprints {
+        builder = builder.fingerprints(path);
+    }
+
+    for path in &args.services {
+        builder = builder.services(path);
+    }
+
+    if let Some(path) = &args.pcap {
+        builder = builder.pcap(path);
+    }
+
+    if let Some(path) = &args.write_pcap {
+        builder = builder.write_pcap(path);
+    }
+
+    if let Some(duration) = args.duration {
+        builder = builder.duration(duration);
+    }
+
+    if let Some(max_idle) = args.max_idle {
+        builder = builder.max_idle(max_idle);
+    }
+
+    if args.resume {
+        let mut endpoints = MappedEndpoints::new();
+        if let Some(path) = &args.save_path {
+            endpoints.extend(snapshot::load(path));
+        }
+        // the database is updated more often
+        if let Some(db) = db {
+            endpoints.extend(db.endpoints()?);
+        }
+        eprintln!("restored {} endpoints", endpoints.len());
+        builder = builder.restore(endpoints);
+    }
+
+    builder.build()
+}
+
+fn run_export(args: &Export) -> Result<(), String> {
+    let columns = export::columns(&args.columns)?;
+    let endpoints = match (&args.db, &args.save_path) {
+        (Some(path), _) => db::read(path)?,
+        (None, Some(path)) => snapshot::load(path),
+        (None, None) => MappedEndpoints::new(),
+    };
+
+    match &args.output {
+        Some(path) => {
+            let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+            export::write_csv(file, &endpoints, &columns)
+        }
+        None => export::write_csv(std::io::stdout().lock(), &endpoints, &columns),
     }
 }
 
 #[cfg(feature = "ui")]
-fn start(args: Arguments) {
-    // create a shared object for the agent and the UI
-    let shared = Arc::new(Mutex::new(HashMap::new()));
+fn start(args: Arguments) -> Result<(), String> {
+    let mut db = open_db(&args)?;
     // create the agent
-    let mut agent = Agent::new(args.query_interval, args.passive, args.address).unwrap();
+    let mut agent = create_agent(&args, db.as_ref())?;
+    start_scan(&mut db)?;
+    // the UI state is shared with the agent
+    let state = agent.endpoints();
 
-    let state = shared.clone();
-    // start the agent on its own thread
-    thread::spawn(move || {
-        agent.start(|endpoints: SharedEndpoints| {
-            if let Ok(mut guard) = shared.lock() {
-                if let Ok(epoints) = endpoints.lock() {
-                    // update the UI state
-                    _ = std::mem::replace(&mut *guard, epoints.clone());
-                    // save to disk
-                    if let Some(path) = &args.save_path {
-                        save_to_path(path, &*epoints);
-                    }
-                }
+    // save to disk
+    let snapshots = start_snapshots(&args, &mut agent);
+    let storage = db.map(|mut db| {
+        let events = agent.subscribe();
+        let endpoints = agent.endpoints();
+        thread::spawn(move || {
+            for event in events {
+                store_event(&mut db, &endpoints, &event);
             }
-        });
+            // the scan is over, flush the final state
+            store_all(&mut db, &endpoints);
+        })
+    });
+
+    // start the agent on its own thread
+    let stop = agent.stop_handle();
+    let (done, finished) = std::sync::mpsc::channel();
+    let agent = thread::spawn(move || {
+        agent.start();
+        let _ = done.send(());
     });
 
     // show the UI
-    ui::run(state);
+    ui::run(state, finished, move || {
+        // the window has been closed or the agent is done, save what's left before exiting
+        stop.stop();
+        agent.join().unwrap();
+        for handle in snapshots.into_iter().chain(storage) {
+            handle.join().unwrap();
+        }
+    });
+
+    Ok(())
 }
 
 #[cfg(not(feature = "ui"))]
-fn start(args: Arguments) {
+fn start(args: Arguments) -> Result<(), String> {
+    let mut db = open_db(&args)?;
     // create the agent
-    let mut agent = Agent::new(args.query_interval, args.passive, args.address).unwrap();
-
-    agent.start(|endpoints: SharedEndpoints| {
-        if let Ok(guard) = endpoints.lock() {
-            clearscreen::clear().unwrap();
-            for endpoint in (*guard).values() {
-                // display
-                display::endpoint(endpoint);
-            }
+    let mut agent = create_agent(&args, db.as_ref())?;
+    start_scan(&mut db)?;
+    let endpoints = agent.endpoints();
+    let events = agent.subscribe();
+    let snapshots = start_snapshots(&args, &mut agent);
 
-            // save to disk
-            if let Some(path) = &args.save_path {
-                save_to_path(path, &*guard);
+    // start the agent on its own thread
+    let stop = agent.stop_handle();
+    let agent = thread::spawn(move || agent.start());
+
+    let stdout = std::io::stdout();
+    for event in events {
+        // save to disk
+        if let Some(db) = &mut db {
+            store_event(db, &endpoints, &event);
+        }
+
+        // display
+        match args.output {
+            Output::Text => display::endpoints(&endpoints),
+            Output::Ndjson => {
+                if let Err(e) = display::ndjson(&mut stdout.lock(), &event) {
+                    // nobody is reading anymore
+                    if e.kind() != std::io::ErrorKind::BrokenPipe {
+                        eprintln!("error writing output: {}", e);
+                    }
+                    stop.stop();
+                    break;
+                }
             }
         }
-    });
+    }
+
+    // the scan is over, flush the final state
+    agent.join().unwrap();
+    if let Some(snapshots) = snapshots {
+        snapshots.join().unwrap();
+    }
+    if let Some(db) = &mut db {
+        store_all(db, &endpoints);
+    }
+    if args.output == Output::Text {
+        display::endpoints(&endpoints);
+    }
+
+    Ok(())
 }
 
 fn main() -> Result<(), String> {
     let args = Arguments::parse();
 
-    start(args);
-
-    Ok(())
+    match &args.command {
+        Some(Command::Export(export)) => run_export(export),
+        None => start(args),
+    }
 }
@@ -1,13 +1,21 @@
 use std::collections::HashMap;
 use std::io;
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::sync::{Arc, Mutex};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 use std::time::{Duration, Instant};
 
-use dns_parser::RData;
-use lazy_static::lazy_static;
+use dns_parser::{QueryType, RData};
+use net2::UdpSocketExt;
 
 use crate::mdns;
+use crate::mdns::event::{Event, Timestamped};
+use crate::mdns::fingerprint;
+use crate::mdns::interface::Interface;
+use crate::mdns::pcap;
+use crate::mdns::services;
+use crate::mdns::transport::{self, PcapTransport, Transport, UdpTransport};
 
 pub type MappedEndpoints = HashMap<IpAddr, mdns::Endpoint>;
 pub type SharedEndpoints = Arc<Mutex<MappedEndpoints>>;
@@ -15,168 +23,258 @@ pub type SharedEndpoints = Arc<Mutex<MappedEndpoints>>;
 // https://datatracker.ietf.org/doc/html/rfc6763#section-9
 pub const DNS_ENUMERATION_SERVICE_NAME: &str = "_services._dns-sd._udp.local";
 
-lazy_static! {
-    pub static ref KNOWN_SERVICES: HashMap<&'static str, &'static str> = {
-        let mut services = HashMap::new();
-
-        services.insert("_services._dns-sd.", "mDNS Enumeration Service");
-        services.insert("_osc.", "MIDI OSC Bridge");
-        services.insert("_apple-midi.", "Apple MIDI Network Driver");
-        services.insert("_adisk.", "Time Capsule Backups");
-        services.insert("_afpovertcp.", "AppleTalk Filing Protocol (AFP)");
-        services.insert("_airdroid.", "AirDroid App");
-        services.insert("_airdrop.", "OSX AirDrop");
-        services.insert("_airplay.", "Apple TV");
-        services.insert("_airport.", "AirPort Base Station");
-        services.insert("_amzn-wplay.", "Amazon Devices");
-        services.insert("_sub._apple-mobdev2.", "OSX Wi-Fi Sync");
-        services.insert("_apple-mobdev2.", "OSX Wi-Fi Sync");
-        services.insert("_apple-sasl.", "Apple Password Server");
-        services.insert("_appletv-v2.", "Apple TV Home Sharing");
-        services.insert("_atc.", "Apple Shared iTunes Library");
-        services.insert("_sketchmirror.", "Sketch App");
-        services.insert("_bcbonjour.", "Sketch App");
-        services.insert("_companion-link.", "Airplay 2");
-        services.insert("_cloud.", "Cloud by Dapile");
-        services.insert("_daap.", "Digital Audio Access Protocol (DAAP)");
-        services.insert("_device-info.", "OSX Device Info");
-        services.insert("_distcc.", "Distributed Compiler");
-        services.insert("_dpap.", "Digital Photo Access Protocol (DPAP)");
-        services.insert("_eppc.", "Remote AppleEvents");
-        services.insert("_esdevice.", "ES File Share App");
-        services.insert("_esfileshare.", "ES File Share App");
-        services.insert("_ftp.", "File Transfer Protocol (FTP)");
-        services.insert("_googlecast.", "Google Cast (Chromecast)");
-        services.insert("_googlezone.", "Google Zone (Chromecast)");
-        services.insert("_hap.", "Apple HomeKit - HomeKit Accessory Protocol");
-        services.insert("_homekit.", "Apple HomeKit");
-        services.insert("_home-sharing.", "iTunes Home Sharing");
-        services.insert("_http.", "Hypertext Transfer Protocol (HTTP)");
-        services.insert("_hudson.", "Jenkins App");
-        services.insert("_hue.", "Philips Hue Smart Bulbs");
-        services.insert("_ica-networking.", "Image Capture Sharing");
-        services.insert("_ichat.", "iChat Instant Messaging Protocol");
-        services.insert("_print._sub._ipp.", "Printers (AirPrint)");
-        services.insert("_cups._sub._ipps.", "Printers");
-        services.insert("_print._sub._ipps.", "Printers");
-        services.insert("_jenkins.", "Jenkins App");
-        services.insert("_apple-lgremote.", "Apple Logic Remote");
-        services.insert("_KeynoteControl.", "OSX Keynote");
-        services.insert("_keynotepair.", "OSX Keynote");
-        services.insert("_mediaremotetv.", "Apple TV Media Remote");
-        services.insert("_nfs.", "Network File System (NFS)");
-        services.insert("_nvstream.", "NVIDIA Shield Game Streaming");
-        services.insert("_androidtvremote.", "Nvidia Shield / Android TV");
-        services.insert("_omnistate.", "OmniGroup (OmniGraffle and other apps)");
-        services.insert("_pdl-datastream.", "PDL Data Stream (Port 9100)");
-        services.insert("_photoshopserver.", "Adobe Photoshop Nav");
-        services.insert("_printer.", "Printers - Line Printer Daemon (LPD/LPR)");
-        services.insert("_raop.", "AirPlay - Remote Audio Output Protocol");
-        services.insert("_readynas.", "Netgear ReadyNAS");
-        services.insert("_rfb.", "OSX Screen Sharing");
-        services.insert("_physicalweb.", "Physical Web");
-        services.insert("_riousbprint.", "Remote I/O USB Printer Protocol");
-        services.insert("_rsp.", "Roku Server Protocol");
-        services.insert("_scanner.", "Scanners");
-        services.insert("_servermgr.", "Server Admin");
-        services.insert("_sftp-ssh.", "Protocol - SFTP");
-        services.insert("_sleep-proxy.", "Wake-on-Network / Bonjour Sleep Proxy");
-        services.insert("_smb.", "Protocol - SMB");
-        services.insert("_spotify-connect.", "Spotify Connect");
-        services.insert("_ssh.", "Protocol - SSH");
-        services.insert("_teamviewer.", "TeamViewer");
-        services.insert("_telnet.", "Remote Login (TELNET)");
-        services.insert("_touch-able.", "Apple TV Remote App (iOS devices)");
-        services.insert("_tunnel.", "Tunnel");
-        services.insert("_udisks-ssh.", "Ubuntu / Raspberry Pi Advertisement");
-        services.insert("_webdav.", "WebDAV File System (WEBDAV)");
-        services.insert("_workstation.", "Workgroup Manager");
-        services.insert("_xserveraid.", "Xserve RAID");
-        services
-    };
-}
-
 pub const ADDR_ANY: Ipv4Addr = Ipv4Addr::new(0, 0, 0, 0);
 pub const MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub const ADDR_ANY_V6: Ipv6Addr = Ipv6Addr::UNSPECIFIED;
+pub const MULTICAST_ADDR_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
 pub const MULTICAST_PORT: u16 = 5353;
 
-const RECV_BUFFER_SIZE: usize = 4096;
+const DNS_HEADER_SIZE: usize = 12;
+// larger queries would be marked as truncated
+const MAX_QUERY_SIZE: usize = 512;
+const MAX_LABEL_SIZE: usize = 63;
+// how often packet readers check if the agent is still running
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+// maximum time the agent waits for packets before running its timers
+const MAX_WAIT: Duration = Duration::from_secs(1);
+// resolved names are asked less and less often, up to once an hour (RFC 6762 section 5.2)
+const MAX_QUERY_INTERVAL: Duration = Duration::from_secs(3600);
 
-pub fn get_service_description(svc_name: &str) -> Option<String> {
-    let svc_name = svc_name.to_ascii_lowercase();
-    for (known_name, known_desc) in &*KNOWN_SERVICES {
-        if svc_name.contains(&known_name.to_ascii_lowercase()) {
-            return Some(known_desc.to_string());
+// a question to ask and what is known about its answers
+struct Asked {
+    name: String,
+    qtype: QueryType,
+    // owners of the records pointing to this name and when they expire, the question is
+    // dropped once none of them is left
+    referrers: HashMap<String, u64>,
+    // when the answers received so far expire
+    answered_until: Option<u64>,
+    // queries between two questions for a name that is already resolved, doubled each
+    // time it's asked (RFC 6762 section 5.2), and how many are left until the next one
+    backoff: u32,
+    skip: u32,
+}
+
+impl Asked {
+    fn new(name: String, qtype: QueryType) -> Self {
+        Self {
+            name,
+            qtype,
+            referrers: HashMap::new(),
+            answered_until: None,
+            backoff: 1,
+            skip: 0,
         }
     }
-    None
+
+    // the service enumeration is always asked
+    fn is_root(&self) -> bool {
+        self.name == DNS_ENUMERATION_SERVICE_NAME
+    }
+
+    fn is_answered_by(&self, rec: &dns_parser::ResourceRecord) -> bool {
+        let qtype = match &rec.data {
+            RData::A(_) => QueryType::A,
+            RData::AAAA(_) => QueryType::AAAA,
+            RData::PTR(_) => QueryType::PTR,
+            RData::SRV(_) => QueryType::SRV,
+            RData::TXT(_) => QueryType::TXT,
+            _ => return false,
+        };
+        self.qtype == qtype && self.name == rec.name.to_string()
+    }
 }
-pub struct Question {
-    services: Vec<String>,
+
+pub(crate) struct Question {
+    // every question to ask
+    questions: Vec<Asked>,
+    // set the QU bit, asking for unicast responses
+    unicast_response: bool,
+    // maximum backoff, in queries
+    max_backoff: u32,
 }
 
 impl Question {
-    pub fn new() -> Self {
-        let services = vec![DNS_ENUMERATION_SERVICE_NAME.to_owned()];
-        Self { services }
+    pub fn new(max_backoff: u32) -> Self {
+        let questions = vec![Asked::new(
+            DNS_ENUMERATION_SERVICE_NAME.to_owned(),
+            QueryType::PTR,
+        )];
+        Self {
+            questions,
+            unicast_response: false,
+            max_backoff: max_backoff.max(1),
+        }
     }
 
-    pub fn query(&self) -> Vec<u8> {
+    // returns the questions that are due split in as many packets as needed, none if
+    // every name is resolved and has been asked recently enough
+    pub fn queries(&mut self) -> Vec<Vec<u8>> {
+        let mut queries = vec![];
         let mut builder = dns_parser::Builder::new_query(0, false);
+        let mut size = DNS_HEADER_SIZE;
+        let mut empty = true;
+
+        for asked in self.questions.iter_mut() {
+            if asked.skip > 0 {
+                asked.skip -= 1;
+                continue;
+            }
+            if asked.answered_until.is_some() {
+                asked.backoff = (asked.backoff * 2).min(self.max_backoff);
+                asked.skip = asked.backoff - 1;
+            }
+
+            // encoded name + type + class
+            let question_size = asked.name.len() + 2 + 4;
+            if !empty && size + question_size > MAX_QUERY_SIZE {
+                queries.push(builder.build().unwrap());
+                builder = dns_parser::Builder::new_query(0, false);
+                size = DNS_HEADER_SIZE;
+            }
 
-        for svc in &self.services {
             builder.add_question(
-                svc,
-                false,
-                dns_parser::QueryType::PTR,
+                &asked.name,
+                self.unicast_response,
+                asked.qtype,
                 dns_parser::QueryClass::IN,
             );
+            size += question_size;
+            empty = false;
         }
 
-        builder.build().unwrap()
+        if !empty {
+            queries.push(builder.build().unwrap());
+        }
+        queries
     }
 
+    // returns true if the question is new
+    fn add(&mut self, name: String, qtype: QueryType, referrer: &str, expires_at: u64) -> bool {
+        // these would not fit in a query, 63 byte labels are legal but dns-parser's builder
+        // panics on them (it asserts part.len() < 63) so they're skipped as well
+        if name.is_empty() || name.split('.').any(|label| label.len() >= MAX_LABEL_SIZE) {
+            return false;
+        }
+
+        let new = !self.is_asking(&name, qtype);
+        if new {
+            self.questions.push(Asked::new(name.clone(), qtype));
+        }
+        if let Some(asked) = self
+            .questions
+            .iter_mut()
+            .find(|asked| asked.name == name && asked.qtype == qtype)
+        {
+            asked.referrers.insert(referrer.to_owned(), expires_at);
+        }
+        new
+    }
+
+    // the record pointing to this name has been withdrawn
+    fn withdraw(&mut self, name: &str, referrer: &str) {
+        for asked in self.questions.iter_mut().filter(|asked| asked.name == name) {
+            asked.referrers.remove(referrer);
+        }
+        self.questions
+            .retain(|asked| asked.is_root() || !asked.referrers.is_empty());
+    }
+
+    fn is_asking(&self, name: &str, qtype: QueryType) -> bool {
+        self.questions
+            .iter()
+            .any(|asked| asked.name == name && asked.qtype == qtype)
+    }
+
+    // follows the DNS-SD chain: service types -> instances -> SRV targets, returns true
+    // if there are new questions to ask
     pub fn add_services<'a>(
         &mut self,
         records: impl Iterator<Item = &'a dns_parser::ResourceRecord<'a>>,
-    ) -> Option<Vec<u8>> {
+        now: u64,
+    ) -> bool {
         let mut changed = false;
 
         for rec in records {
             let svc_name = rec.name.to_string();
-            if svc_name == DNS_ENUMERATION_SERVICE_NAME {
-                if let RData::PTR(name) = rec.data {
-                    let data_name = name.0.to_string();
-                    if !self.services.contains(&data_name) {
-                        self.services.push(data_name);
-                        changed = true;
-                    }
+            let expires_at = now + rec.ttl as u64;
+            // goodbye packet, stop asking for what the record was pointing to
+            if rec.ttl == 0 {
+                match &rec.data {
+                    RData::PTR(name) => self.withdraw(&name.0.to_string(), &svc_name),
+                    RData::SRV(srv) => self.withdraw(&srv.target.to_string(), &svc_name),
+                    _ => {}
                 }
+                continue;
+            }
+
+            match &rec.data {
+                // service type, browse for its instances
+                RData::PTR(name) if svc_name == DNS_ENUMERATION_SERVICE_NAME => {
+                    changed |= self.add(name.0.to_string(), QueryType::PTR, &svc_name, expires_at);
+                }
+                // instance of a service type we're browsing, resolve it
+                RData::PTR(name) if self.is_asking(&svc_name, QueryType::PTR) => {
+                    let instance = name.0.to_string();
+                    changed |= self.add(instance.clone(), QueryType::SRV, &svc_name, expires_at);
+                    changed |= self.add(instance, QueryType::TXT, &svc_name, expires_at);
+                }
+                // resolve the host the instance is running on
+                RData::SRV(srv) => {
+                    let target = srv.target.to_string();
+                    changed |= self.add(target.clone(), QueryType::A, &svc_name, expires_at);
+                    changed |= self.add(target, QueryType::AAAA, &svc_name, expires_at);
+                }
+                _ => {}
+            }
+
+            for asked in self
+                .questions
+                .iter_mut()
+                .filter(|asked| asked.is_answered_by(rec))
+            {
+                asked.answered_until = asked.answered_until.max(Some(expires_at));
             }
         }
 
-        if changed {
-            Some(self.query())
-        } else {
-            None
+        changed
+    }
+
+    // drops the questions nothing points to anymore, and asks again at the query
+    // interval for the names whose answers expired
+    pub fn expire(&mut self, now: u64) {
+        for asked in self.questions.iter_mut() {
+            asked.referrers.retain(|_, expires_at| *expires_at > now);
+            if matches!(asked.answered_until, Some(expires_at) if expires_at <= now) {
+                asked.answered_until = None;
+                asked.backoff = 1;
+                asked.skip = 0;
+            }
         }
+        self.questions
+            .retain(|asked| asked.is_root() || !asked.referrers.is_empty());
     }
 }
 
-pub struct Channel {
+// (channel index, received packet)
+type RawPacket = (usize, transport::Packet);
+
+pub(crate) struct Channel {
     passive: bool,
-    address: SocketAddr,
-    socket: std::net::UdpSocket,
-    recv_buffer: Vec<u8>,
+    interface: Option<Interface>,
+    addresses: Vec<SocketAddr>,
+    transport: Box<dyn Transport>,
+    // only set when querying a single host by unicast
+    peer: Option<SocketAddr>,
+    local_port: u16,
 
     query_time: Duration,
     last_query: Option<Instant>,
     question: Question,
-    query_data: Vec<u8>,
 }
 
 #[cfg(not(target_os = "windows"))]
-fn create_socket() -> io::Result<std::net::UdpSocket> {
+fn create_socket_v4() -> io::Result<std::net::UdpSocket> {
     use net2::unix::UnixUdpBuilderExt;
 
     net2::UdpBuilder::new_v4()?
@@ -186,144 +284,998 @@ fn create_socket() -> io::Result<std::net::UdpSocket> {
 }
 
 #[cfg(target_os = "windows")]
-fn create_socket() -> io::Result<std::net::UdpSocket> {
+fn create_socket_v4() -> io::Result<std::net::UdpSocket> {
     net2::UdpBuilder::new_v4()?
         .reuse_address(true)?
         .bind((ADDR_ANY, MULTICAST_PORT))
 }
 
+#[cfg(not(target_os = "windows"))]
+fn create_socket_v6() -> io::Result<std::net::UdpSocket> {
+    use net2::unix::UnixUdpBuilderExt;
+
+    net2::UdpBuilder::new_v6()?
+        .only_v6(true)?
+        .reuse_address(true)?
+        .reuse_port(true)?
+        .bind((ADDR_ANY_V6, MULTICAST_PORT))
+}
+
+#[cfg(target_os = "windows")]
+fn create_socket_v6() -> io::Result<std::net::UdpSocket> {
+    net2::UdpBuilder::new_v6()?
+        .only_v6(true)?
+        .reuse_address(true)?
+        .bind((ADDR_ANY_V6, MULTICAST_PORT))
+}
+
 impl Channel {
-    pub fn new(query_time_secs: u64, passive: bool) -> Result<Self, String> {
+    pub fn new_v4(
+        query_time_secs: u64,
+        passive: bool,
+        interface: Option<Interface>,
+    ) -> Result<Self, String> {
         let address = SocketAddr::new(MULTICAST_ADDR.into(), MULTICAST_PORT);
-        let recv_buffer = vec![0; RECV_BUFFER_SIZE];
+        // when no interface is specified the OS will pick one
+        let iface_addr = match &interface {
+            Some(iface) => iface
+                .first_ipv4()
+                .ok_or(format!("interface {} has no IPv4 address", iface.name))?,
+            None => ADDR_ANY,
+        };
 
-        let socket = create_socket().map_err(|e| e.to_string())?;
+        let socket = create_socket_v4().map_err(|e| e.to_string())?;
         socket
             .set_multicast_loop_v4(false)
             .map_err(|e| e.to_string())?;
         socket
-            .join_multicast_v4(&MULTICAST_ADDR, &ADDR_ANY)
+            .join_multicast_v4(&MULTICAST_ADDR, &iface_addr)
             .map_err(|e| e.to_string())?;
+        if interface.is_some() {
+            socket
+                .set_multicast_if_v4(&iface_addr)
+                .map_err(|e| e.to_string())?;
+        }
+
+        Ok(Self::with_transport(
+            Box::new(UdpTransport::new(socket)),
+            interface,
+            vec![address],
+            query_time_secs,
+            passive,
+        ))
+    }
+
+    pub fn new_v6(
+        query_time_secs: u64,
+        passive: bool,
+        interface: Option<Interface>,
+    ) -> Result<Self, String> {
+        // when no interface is specified use all of them
+        let indexes: Vec<u32> = match &interface {
+            Some(iface) => vec![iface.index.ok_or(format!(
+                "interface {} has no IPv6 link local address",
+                iface.name
+            ))?],
+            None => Interface::get_all()?
+                .iter()
+                .filter_map(|iface| iface.index)
+                .collect(),
+        };
+
+        let socket = create_socket_v6().map_err(|e| e.to_string())?;
+        socket
+            .set_multicast_loop_v6(false)
+            .map_err(|e| e.to_string())?;
+        if let Some(index) = interface.as_ref().and_then(|iface| iface.index) {
+            socket
+                .set_multicast_if_v6(index)
+                .map_err(|e| e.to_string())?;
+        }
+
+        // join the group on each interface, queries will be sent on each one of them
+        let mut addresses = vec![];
+        for index in indexes {
+            if socket.join_multicast_v6(&MULTICAST_ADDR_V6, index).is_ok() {
+                addresses.push(SocketAddr::V6(SocketAddrV6::new(
+                    MULTICAST_ADDR_V6,
+                    MULTICAST_PORT,
+                    0,
+                    index,
+                )));
+            }
+        }
+
+        if addresses.is_empty() {
+            return Err("could not join the IPv6 multicast group on any interface".to_string());
+        }
+
+        Ok(Self::with_transport(
+            Box::new(UdpTransport::new(socket)),
+            interface,
+            addresses,
+            query_time_secs,
+            passive,
+        ))
+    }
 
+    // sends the queries straight to the peer from an ephemeral port, which makes it answer
+    // by unicast as it would to a legacy resolver (RFC 6762 section 6.7), across routed
+    // links too
+    pub fn new_unicast(query_time_secs: u64, peer: SocketAddr) -> Result<Self, String> {
+        let any = if peer.is_ipv6() {
+            SocketAddr::new(ADDR_ANY_V6.into(), 0)
+        } else {
+            SocketAddr::new(ADDR_ANY.into(), 0)
+        };
+
+        let socket = std::net::UdpSocket::bind(any).map_err(|e| e.to_string())?;
+        let local_port = socket.local_addr().map_err(|e| e.to_string())?.port();
+
+        let mut channel = Self::with_transport(
+            Box::new(UdpTransport::new(socket)),
+            None,
+            vec![peer],
+            query_time_secs,
+            false,
+        );
+        channel.peer = Some(peer);
+        channel.local_port = local_port;
+
+        Ok(channel)
+    }
+
+    pub fn with_transport(
+        transport: Box<dyn Transport>,
+        interface: Option<Interface>,
+        addresses: Vec<SocketAddr>,
+        query_time_secs: u64,
+        passive: bool,
+    ) -> Self {
         let query_time = Duration::from_secs(query_time_secs);
         let last_query = None;
-        let question = Question::new();
-        let query_data = question.query();
+        let max_backoff = MAX_QUERY_INTERVAL.as_secs() / query_time_secs.max(1);
+        let question = Question::new(max_backoff as u32);
 
-        Ok(Self {
+        Self {
             passive,
-            address,
-            socket,
-            recv_buffer,
+            interface,
+            addresses,
+            transport,
+            peer: None,
+            local_port: MULTICAST_PORT,
             query_time,
             last_query,
             question,
-            query_data,
-        })
+        }
     }
 
-    pub fn send_query_if_needed(&mut self) {
-        if !self.passive
-            && (self.last_query.is_none() || self.last_query.unwrap().elapsed() >= self.query_time)
-        {
-            if let Err(e) = self.socket.send_to(&self.query_data, self.address) {
-                println!("error sending multicast query: {:?}", e);
-            } else {
-                self.last_query = Some(Instant::now());
-            }
+    pub fn set_unicast_response(&mut self, enabled: bool) {
+        self.question.unicast_response = enabled;
+    }
+
+    // stops asking for the names that are gone
+    pub fn expire(&mut self, now: u64) {
+        self.question.expire(now);
+    }
+
+    // returns how long until the next query is due, or None if in passive mode
+    pub fn next_query_in(&self) -> Option<Duration> {
+        if self.passive {
+            None
+        } else if let Some(last_query) = self.last_query {
+            Some(self.query_time.saturating_sub(last_query.elapsed()))
+        } else {
+            Some(Duration::ZERO)
         }
     }
 
-    pub fn read_packet(&mut self) -> Option<(SocketAddr, dns_parser::Packet)> {
-        let (count, source) = self.socket.recv_from(&mut self.recv_buffer).unwrap();
-        if count > 0 {
-            let parsed = dns_parser::Packet::parse(&self.recv_buffer[..count]);
-            if let Ok(packet) = parsed {
-                // check new services to discover
-                let records = packet.answers.iter().chain(packet.additional.iter());
-                let new_query = self.question.add_services(records);
-                if let Some(new_query) = new_query {
-                    // trigger new query
-                    self.query_data = new_query;
-                    self.last_query = None;
+    // returns the destination and data of the queries that have been sent
+    pub fn send_query_if_needed(&mut self) -> Vec<(SocketAddr, Vec<u8>)> {
+        let mut sent = vec![];
+        if self.next_query_in() == Some(Duration::ZERO) {
+            let queries = self.question.queries();
+            for address in &self.addresses {
+                for query in &queries {
+                    match self.transport.send_to(query, address) {
+                        Ok(()) => sent.push((*address, query.to_owned())),
+                        Err(e) => {
+                            eprintln!("error sending query to {}: {:?}", address, e)
+                        }
+                    }
                 }
+            }
+            // retry at the next interval in case of errors
+            self.last_query = Some(Instant::now());
+        }
+        sent
+    }
+
+    pub fn is_ipv6(&self) -> bool {
+        matches!(self.addresses.first(), Some(SocketAddr::V6(_)))
+    }
+
+    // the multicast group this channel receives from, or our address for unicast
+    pub fn group(&self) -> SocketAddr {
+        if self.peer.is_some() {
+            self.local_address()
+        } else if self.is_ipv6() {
+            SocketAddr::new(IpAddr::V6(MULTICAST_ADDR_V6), MULTICAST_PORT)
+        } else {
+            SocketAddr::new(IpAddr::V4(MULTICAST_ADDR), MULTICAST_PORT)
+        }
+    }
+
+    // the address queries are sent from, unspecified if the OS picks the interface
+    pub fn local_address(&self) -> SocketAddr {
+        let ip = match (&self.interface, self.is_ipv6()) {
+            (Some(iface), false) => iface.first_ipv4().map(IpAddr::V4),
+            (Some(iface), true) => iface
+                .ipv6
+                .iter()
+                .map(|(ip, _)| *ip)
+                .find(|ip| ip.segments()[0] & 0xffc0 == 0xfe80)
+                .map(IpAddr::V6),
+            (None, _) => None,
+        };
+
+        match ip {
+            Some(ip) => SocketAddr::new(ip, self.local_port),
+            None if self.is_ipv6() => SocketAddr::new(IpAddr::V6(ADDR_ANY_V6), self.local_port),
+            None => SocketAddr::new(IpAddr::V4(ADDR_ANY), self.local_port),
+        }
+    }
+
+    // name and description of this channel
+    pub fn describe(&self) -> (String, String) {
+        let version = if self.is_ipv6() { "IPv6" } else { "IPv4" };
+        if let Some(peer) = &self.peer {
+            return ("unicast".to_string(), format!("unicast mDNS to {}", peer));
+        }
+
+        match &self.interface {
+            Some(iface) => (
+                iface.name.to_owned(),
+                format!("mDNS over {} on {}", version, iface.name),
+            ),
+            None => ("any".to_string(), format!("mDNS over {}", version)),
+        }
+    }
+
+    // since all sockets are bound to the same port, the OS can deliver to this channel
+    // packets that have been received on other interfaces
+    pub fn accepts(&self, source: &SocketAddr) -> bool {
+        if let Some(peer) = &self.peer {
+            return source.ip() == peer.ip();
+        }
+
+        match &self.interface {
+            Some(iface) => iface.contains(source),
+            None => true,
+        }
+    }
+
+    // false if the packets are not coming from the network this host is on
+    pub fn is_live(&self) -> bool {
+        self.transport.is_live()
+    }
+
+    // returns the name of the interface the source address has been seen on
+    pub fn interface_for(&self, source: &SocketAddr) -> Option<String> {
+        match &self.interface {
+            Some(iface) => Some(iface.name.to_owned()),
+            None if self.is_live() => Interface::find_for(source),
+            None => None,
+        }
+    }
+
+    pub fn start_reader(
+        &self,
+        id: usize,
+        packets: mpsc::Sender<RawPacket>,
+        running: Arc<AtomicBool>,
+    ) -> io::Result<()> {
+        let mut transport = self.transport.try_clone()?;
 
-                // only interested in responses
-                if !packet.header.query {
-                    return Some((source, packet));
+        thread::spawn(move || {
+            while running.load(Ordering::Relaxed) {
+                match transport.recv(READ_TIMEOUT) {
+                    Ok(Some(packet)) => {
+                        // stop if the agent is gone
+                        if packets.send((id, packet)).is_err() {
+                            break;
+                        }
+                    }
+                    // read timeout, check if we're still running
+                    Ok(None) => {}
+                    // nothing more to receive
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                    Err(e) => eprintln!("error receiving packet: {:?}", e),
                 }
-            } else {
-                println!("error parsing packet: {:?}", parsed);
             }
+        });
+
+        Ok(())
+    }
+
+    pub fn parse_packet<'a>(&mut self, data: &'a [u8], now: u64) -> Option<dns_parser::Packet<'a>> {
+        let parsed = dns_parser::Packet::parse(data);
+        if let Ok(packet) = parsed {
+            // check new services to discover
+            let records = packet.answers.iter().chain(packet.additional.iter());
+            if self.question.add_services(records, now) {
+                // trigger new query
+                self.last_query = None;
+            }
+
+            return Some(packet);
+        } else {
+            eprintln!("error parsing packet: {:?}", parsed);
         }
         None
     }
 }
 
+/// Builds an [`Agent`] with the given discovery options.
+pub struct AgentBuilder {
+    query_interval: u64,
+    passive: bool,
+    filter_for: Option<String>,
+    ipv4: bool,
+    ipv6: bool,
+    interfaces: Vec<String>,
+    duration: Option<Duration>,
+    max_idle: Option<Duration>,
+    fingerprints: Vec<String>,
+    services: Vec<String>,
+    pcap: Option<String>,
+    write_pcap: Option<String>,
+    transports: Vec<Box<dyn Transport>>,
+    targets: Vec<SocketAddr>,
+    unicast_response: bool,
+    restore: MappedEndpoints,
+}
+
+impl Default for AgentBuilder {
+    fn default() -> Self {
+        Self {
+            query_interval: 5,
+            passive: false,
+            filter_for: None,
+            ipv4: true,
+            ipv6: true,
+            interfaces: vec![],
+            duration: None,
+            max_idle: None,
+            fingerprints: vec![],
+            services: vec![],
+            pcap: None,
+            write_pcap: None,
+            transports: vec![],
+            targets: vec![],
+            unicast_response: false,
+            restore: HashMap::new(),
+        }
+    }
+}
+
+impl AgentBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// When in active mode send mDNS queries at this interval, in seconds.
+    pub fn query_interval(mut self, secs: u64) -> Self {
+        self.query_interval = secs;
+        self
+    }
+
+    /// Do not execute queries, listen only.
+    pub fn passive(mut self, passive: bool) -> Self {
+        self.passive = passive;
+        self
+    }
+
+    /// Only report results from this address.
+    pub fn filter_for(mut self, address: Option<String>) -> Self {
+        self.filter_for = address;
+        self
+    }
+
+    /// Enable or disable discovery over IPv4.
+    pub fn ipv4(mut self, enabled: bool) -> Self {
+        self.ipv4 = enabled;
+        self
+    }
+
+    /// Enable or disable discovery over IPv6.
+    pub fn ipv6(mut self, enabled: bool) -> Self {
+        self.ipv6 = enabled;
+        self
+    }
+
+    /// Run discovery on this interface, by name or address. Can be called multiple times,
+    /// if never called the interfaces are picked by the OS.
+    pub fn interface(mut self, name_or_address: &str) -> Self {
+        self.interfaces.push(name_or_address.to_owned());
+        self
+    }
+
+    /// Stop discovery after this many seconds.
+    pub fn duration(mut self, secs: u64) -> Self {
+        self.duration = Some(Duration::from_secs(secs));
+        self
+    }
+
+    /// Stop discovery once nothing new has been seen for this many seconds.
+    pub fn max_idle(mut self, secs: u64) -> Self {
+        self.max_idle = Some(Duration::from_secs(secs));
+        self
+    }
+
+    /// Query this host directly by unicast instead of using multicast, usually on port 5353.
+    /// Can be called multiple times, the agent is always active when a target is set.
+    /// Targets can't be combined with interfaces and their IP version must be enabled.
+    pub fn target(mut self, address: SocketAddr) -> Self {
+        self.targets.push(address);
+        self
+    }
+
+    /// Set the QU bit of the questions, asking the responders to answer by unicast.
+    pub fn unicast_response(mut self, enabled: bool) -> Self {
+        self.unicast_response = enabled;
+        self
+    }
+
+    /// Start from these endpoints, such as the ones stored by a previous scan. They're
+    /// marked as stale and their records don't expire until they're seen again.
+    pub fn restore(mut self, endpoints: MappedEndpoints) -> Self {
+        self.restore = endpoints;
+        self
+    }
+
+    /// Load extra fingerprinting rules from this JSON file. Can be called multiple times.
+    pub fn fingerprints(mut self, path: &str) -> Self {
+        self.fingerprints.push(path.to_owned());
+        self
+    }
+
+    /// Load extra service descriptions from this CSV file. Can be called multiple times.
+    pub fn services(mut self, path: &str) -> Self {
+        self.services.push(path.to_owned());
+        self
+    }
+
+    /// Read the packets from this pcap or pcapng file instead of the network, using the
+    /// capture timestamps. No socket is created and no query is sent.
+    pub fn pcap(mut self, path: &str) -> Self {
+        self.pcap = Some(path.to_owned());
+        self
+    }
+
+    /// Write every packet sent or received by the agent to this pcapng file.
+    pub fn write_pcap(mut self, path: &str) -> Self {
+        self.write_pcap = Some(path.to_owned());
+        self
+    }
+
+    /// Run discovery over this transport instead of the network, queries are sent to the
+    /// IPv4 multicast group. Can be called multiple times.
+    pub fn transport(mut self, transport: impl Transport + 'static) -> Self {
+        self.transports.push(Box::new(transport));
+        self
+    }
+
+    /// Create the agent and its sockets.
+    pub fn build(self) -> Result<Agent, String> {
+        Agent::new(self)
+    }
+}
+
+/// Stops a running [`Agent`] from another thread.
+#[derive(Clone)]
+pub struct StopHandle {
+    running: Arc<AtomicBool>,
+}
+
+impl StopHandle {
+    pub fn stop(&self) {
+        self.running.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Discovers mDNS endpoints and their services.
 pub struct Agent {
-    channel: Channel,
+    passive: bool,
+    channels: Vec<Channel>,
     endpoints: SharedEndpoints,
     filter_for: Option<String>,
+    running: Arc<AtomicBool>,
+    subscribers: Vec<mpsc::Sender<Timestamped>>,
+
+    duration: Option<Duration>,
+    max_idle: Option<Duration>,
+    started_at: Instant,
+    last_change: Instant,
+
+    // offline analysis
+    pcap: Option<String>,
+    ipv4: bool,
+    ipv6: bool,
+    // time of the last packet that carried its own timestamp
+    clock: Option<u64>,
+    // records the live traffic
+    writer: Option<pcap::Writer>,
+    // the builtin fingerprinting rules and service descriptions, and the ones loaded for
+    // this agent
+    rules: fingerprint::Rules,
+    services: services::Services,
 }
 
 impl Agent {
-    pub fn new(
-        query_time_secs: u64,
-        passive: bool,
-        filter_for: Option<String>,
-    ) -> Result<Self, String> {
-        let channel = Channel::new(query_time_secs, passive)?;
-        let endpoints = Arc::new(Mutex::new(HashMap::new()));
+    pub fn builder() -> AgentBuilder {
+        AgentBuilder::new()
+    }
+
+    fn new(options: AgentBuilder) -> Result<Self, String> {
+        let AgentBuilder {
+            query_interval: query_time_secs,
+            passive,
+            filter_for,
+            ipv4,
+            ipv6,
+            interfaces,
+            duration,
+            max_idle,
+            fingerprints,
+            services: service_files,
+            pcap,
+            write_pcap,
+            transports,
+            targets,
+            unicast_response,
+            mut restore,
+        } = options;
+        // queries are always sent to the targets
+        let passive = passive && targets.is_empty();
+
+        // targets are queried from a socket of their own, not on the interfaces
+        if !targets.is_empty() && !interfaces.is_empty() {
+            return Err("targets can't be combined with interfaces".to_owned());
+        }
+        for target in &targets {
+            if (target.is_ipv4() && !ipv4) || (target.is_ipv6() && !ipv6) {
+                return Err(format!(
+                    "can't query {}, its IP version has been disabled",
+                    target
+                ));
+            }
+        }
+
+        let mut rules = fingerprint::Rules::default();
+        for path in &fingerprints {
+            let count = rules.load(path)?;
+            eprintln!("loaded {} fingerprinting rules from {}", count, path);
+        }
+
+        let mut services = services::Services::default();
+        for path in &service_files {
+            let count = services.load(path)?;
+            eprintln!("loaded {} service descriptions from {}", count, path);
+        }
+
+        let mut channels = vec![];
+
+        if !transports.is_empty() {
+            let group = SocketAddr::new(MULTICAST_ADDR.into(), MULTICAST_PORT);
+            for transport in transports {
+                channels.push(Channel::with_transport(
+                    transport,
+                    None,
+                    vec![group],
+                    query_time_secs,
+                    passive,
+                ));
+            }
+        } else if let Some(path) = &pcap {
+            // offline analysis, captures can't be queried
+            let transport = PcapTransport::open(path)?;
+            channels.push(Channel::with_transport(
+                Box::new(transport),
+                None,
+                vec![],
+                query_time_secs,
+                true,
+            ));
+        } else if !targets.is_empty() {
+            for target in targets {
+                channels.push(Channel::new_unicast(query_time_secs, target)?);
+            }
+        } else if interfaces.is_empty() {
+            if ipv4 {
+                channels.push(Channel::new_v4(query_time_secs, passive, None)?);
+            }
+
+            if ipv6 {
+                match Channel::new_v6(query_time_secs, passive, None) {
+                    Ok(channel) => channels.push(channel),
+                    // not fatal if we can still work over IPv4
+                    Err(e) if !channels.is_empty() => eprintln!("IPv6 discovery disabled: {}", e),
+                    Err(e) => return Err(e),
+                }
+            }
+        } else {
+            // one channel per interface and IP version
+            for name in &interfaces {
+                let iface = Interface::find(name)?;
+
+                if ipv4 {
+                    match Channel::new_v4(query_time_secs, passive, Some(iface.clone())) {
+                        Ok(channel) => channels.push(channel),
+                        Err(e) => eprintln!("IPv4 discovery disabled on {}: {}", name, e),
+                    }
+                }
+
+                if ipv6 {
+                    match Channel::new_v6(query_time_secs, passive, Some(iface)) {
+                        Ok(channel) => channels.push(channel),
+                        Err(e) => eprintln!("IPv6 discovery disabled on {}: {}", name, e),
+                    }
+                }
+            }
+        }
+
+        if channels.is_empty() {
+            return Err("no channels available for discovery".to_string());
+        }
+
+        for channel in &mut channels {
+            channel.set_unicast_response(unicast_response);
+        }
+
+        // one capture interface for each channel, with the same id
+        let writer = match write_pcap {
+            Some(path) => {
+                let mut writer = pcap::Writer::create(&path)?;
+                for channel in &channels {
+                    let (name, description) = channel.describe();
+                    writer.add_interface(&name, &description, channel.interface.as_ref())?;
+                }
+                Some(writer)
+            }
+            None => None,
+        };
+
+        // not seen by this scan yet
+        for endpoint in restore.values_mut() {
+            endpoint.stale = true;
+        }
+        let endpoints = Arc::new(Mutex::new(restore));
+        let running = Arc::new(AtomicBool::new(true));
 
         Ok(Self {
-            channel,
+            passive,
+            channels,
             endpoints,
             filter_for,
+            running,
+            subscribers: vec![],
+            duration,
+            max_idle,
+            started_at: Instant::now(),
+            last_change: Instant::now(),
+            pcap,
+            ipv4,
+            ipv6,
+            clock: None,
+            writer,
+            rules,
+            services,
         })
     }
 
-    pub fn start(&mut self, cb: impl Fn(SharedEndpoints)) {
-        println!(
-            "started in {} mode ...",
-            if self.channel.passive {
-                "passive"
-            } else {
-                "active"
+    /// Returns a handle that can be used to stop the agent.
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle {
+            running: self.running.clone(),
+        }
+    }
+
+    /// The endpoints discovered so far.
+    pub fn endpoints(&self) -> SharedEndpoints {
+        self.endpoints.clone()
+    }
+
+    /// Receive the discovery events with the time they happened at, the receiver is closed
+    /// when the agent stops.
+    pub fn subscribe(&mut self) -> mpsc::Receiver<Timestamped> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// Run discovery until stopped, until the duration or idle limits are reached or
+    /// until all channels are closed. When reading from a capture file, run until all
+    /// of its packets have been processed.
+    pub fn start(&mut self) {
+        match &self.pcap {
+            Some(path) => eprintln!("reading {} ...", path),
+            None => eprintln!(
+                "started in {} mode ...",
+                if self.passive { "passive" } else { "active" }
+            ),
+        }
+
+        self.run();
+
+        // stop the readers
+        self.running.store(false, Ordering::SeqCst);
+        // close the event streams
+        self.subscribers.clear();
+    }
+
+    fn run(&mut self) {
+        self.started_at = Instant::now();
+        self.last_change = self.started_at;
+
+        // each channel reads packets on its own thread and sends them here
+        let (tx, rx) = mpsc::channel();
+        for (id, channel) in self.channels.iter().enumerate() {
+            if let Err(e) = channel.start_reader(id, tx.clone(), self.running.clone()) {
+                eprintln!("error starting packet reader: {:?}", e);
+            }
+        }
+        drop(tx);
+
+        while self.running.load(Ordering::Relaxed) {
+            if self.time_left() == Some(Duration::ZERO) {
+                eprintln!("scan limit reached, stopping ...");
+                break;
             }
-        );
 
-        loop {
             // send query if interval has elapsed and we're not in passive mode
-            self.channel.send_query_if_needed();
-
-            // wait for a response packet
-            if let Some((source, packet)) = self.channel.read_packet() {
-                // skip if we need to filter by address and this is not it
-                if let Some(ref address) = self.filter_for {
-                    if source.ip().to_string() != *address {
-                        continue;
-                    }
+            for id in 0..self.channels.len() {
+                let source = self.channels[id].local_address();
+                for (destination, query) in self.channels[id].send_query_if_needed() {
+                    self.record(id, pcap::Direction::Outbound, source, destination, &query);
                 }
+            }
 
-                // check if we have any answers
-                if !packet.answers.is_empty() || !packet.additional.is_empty() {
-                    // collect answers + additional records
-                    let records = packet.answers.iter().chain(packet.additional.iter());
-                    let source_ip = source.ip();
-                    // update endpoints
-                    if let Ok(mut guard) = self.endpoints.lock() {
-                        if let Some(endpoint) = guard.get_mut(&source_ip) {
-                            // known endpoint, update services and properties
-                            endpoint.add_services(records)
-                        } else {
-                            // new endpoint
-                            guard.insert(source_ip, mdns::Endpoint::with_services(source, records));
-                        }
+            // wait for a packet from any channel, or until the next timer is due
+            match rx.recv_timeout(self.next_wait()) {
+                Ok((id, packet)) => {
+                    let events = self.on_packet(id, packet);
+                    // repeated queries, including ours, are not something new
+                    if events
+                        .iter()
+                        .any(|e| !matches!(e, Event::QueryObserved { .. }))
+                    {
+                        self.last_change = Instant::now();
                     }
-                    // pass to callback
-                    cb(self.endpoints.clone());
+                    self.emit(events);
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                // all readers are gone
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+
+            // evict expired records
+            let events = self.expire(self.now());
+            self.emit(events);
+        }
+    }
+
+    // writes a packet sent or received by a channel to the capture file, if any
+    fn record(
+        &mut self,
+        id: usize,
+        direction: pcap::Direction,
+        source: SocketAddr,
+        destination: SocketAddr,
+        data: &[u8],
+    ) {
+        if let Some(writer) = &mut self.writer {
+            if let Err(e) = writer.write(id as u32, direction, &source, &destination, data) {
+                eprintln!("error writing packet capture, recording stopped: {}", e);
+                self.writer = None;
+            }
+        }
+    }
+
+    fn emit(&mut self, events: Vec<Event>) {
+        let timestamp = self.now();
+        for event in events {
+            let event = Timestamped { timestamp, event };
+            // drop the subscribers that are gone
+            self.subscribers
+                .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        }
+    }
+
+    // returns the events describing what expired
+    fn expire(&mut self, now: u64) -> Vec<Event> {
+        for channel in self.channels.iter_mut() {
+            channel.expire(now);
+        }
+
+        let mut events = vec![];
+        if let Ok(mut guard) = self.endpoints.lock() {
+            for endpoint in guard.values_mut() {
+                events.extend(endpoint.expire(now, &self.rules));
+            }
+        }
+        events
+    }
+
+    // the capture time when replaying packets, the current time otherwise
+    fn now(&self) -> u64 {
+        self.clock.unwrap_or_else(mdns::now)
+    }
+
+    // returns how long until the duration or idle limit is reached, if any
+    fn time_left(&self) -> Option<Duration> {
+        let duration = self
+            .duration
+            .map(|duration| duration.saturating_sub(self.started_at.elapsed()));
+        let idle = self
+            .max_idle
+            .map(|max_idle| max_idle.saturating_sub(self.last_change.elapsed()));
+
+        match (duration, idle) {
+            (Some(duration), Some(idle)) => Some(duration.min(idle)),
+            (duration, idle) => duration.or(idle),
+        }
+    }
+
+    // returns how long the loop can wait for packets before the next timer is due
+    fn next_wait(&self) -> Duration {
+        self.channels
+            .iter()
+            .filter_map(|channel| channel.next_query_in())
+            .chain(self.time_left())
+            .fold(MAX_WAIT, |wait, next| wait.min(next))
+    }
+
+    // returns the events describing what changed
+    fn on_packet(&mut self, id: usize, packet: transport::Packet) -> Vec<Event> {
+        // records expire according to the capture time
+        if let Some(timestamp) = packet.timestamp {
+            self.clock = Some(timestamp);
+        }
+
+        let source = packet.source;
+        if (source.is_ipv4() && !self.ipv4) || (source.is_ipv6() && !self.ipv6) {
+            return vec![];
+        }
+
+        let channel = &self.channels[id];
+        if !channel.accepts(&source) {
+            return vec![];
+        }
+
+        // the group of the channel if the transport doesn't know where it's been sent to
+        let destination = packet.destination.unwrap_or_else(|| channel.group());
+        self.record(
+            id,
+            pcap::Direction::Inbound,
+            source,
+            destination,
+            &packet.data,
+        );
+
+        let now = self.now();
+        match self.channels[id].parse_packet(&packet.data, now) {
+            Some(message) => {
+                let live = self.channels[id].is_live();
+                // only needed for new endpoints
+                let interface = if self.is_known(&source.ip()) {
+                    None
+                } else if packet.interface.is_some() {
+                    packet.interface.clone()
+                } else {
+                    self.channels[id].interface_for(&source)
+                };
+                self.on_message(source, interface, &message, live, now)
+            }
+            None => vec![],
+        }
+    }
+
+    fn is_known(&self, address: &IpAddr) -> bool {
+        match self.endpoints.lock() {
+            Ok(guard) => guard.contains_key(address),
+            Err(_) => false,
+        }
+    }
+
+    fn new_endpoint(
+        &self,
+        address: IpAddr,
+        interface: Option<String>,
+        live: bool,
+        now: u64,
+    ) -> mdns::Endpoint {
+        let mut endpoint = mdns::Endpoint::new(address, interface, now);
+        // captured hosts are not on our network
+        if live {
+            endpoint.lookup();
+        }
+        endpoint
+    }
+
+    // handles a DNS message received from source at the given time, returns the events
+    // describing what changed
+    fn on_message(
+        &self,
+        source: SocketAddr,
+        interface: Option<String>,
+        packet: &dns_parser::Packet,
+        live: bool,
+        now: u64,
+    ) -> Vec<Event> {
+        // skip if we need to filter by address and this is not it
+        if let Some(ref address) = self.filter_for {
+            if source.ip().to_string() != *address {
+                return vec![];
+            }
+        }
+
+        let source_ip = source.ip();
+
+        if packet.header.query {
+            if packet.questions.is_empty() {
+                return vec![];
+            }
+
+            let mut events = vec![];
+            // known answers are sent as the answers of a query
+            if let Ok(mut guard) = self.endpoints.lock() {
+                if let Some(endpoint) = guard.get_mut(&source_ip) {
+                    events.extend(endpoint.add_interests(&packet.questions, &packet.answers, now));
+                } else {
+                    // new endpoint that's only asking for now
+                    let mut endpoint = self.new_endpoint(source_ip, interface, live, now);
+                    // not stale, nothing to report but the discovery
+                    endpoint.add_interests(&packet.questions, &packet.answers, now);
+                    guard.insert(source_ip, endpoint.clone());
+                    events.push(Event::EndpointDiscovered {
+                        endpoint: Box::new(endpoint),
+                    });
                 }
             }
+
+            events.push(Event::QueryObserved {
+                source: source_ip,
+                questions: packet
+                    .questions
+                    .iter()
+                    .map(|question| question.qname.to_string())
+                    .collect(),
+            });
+            return events;
         }
+
+        // check if we have any answers
+        if !packet.answers.is_empty() || !packet.additional.is_empty() {
+            // collect answers + additional records
+            let records = packet.answers.iter().chain(packet.additional.iter());
+            // update endpoints
+            if let Ok(mut guard) = self.endpoints.lock() {
+                if let Some(endpoint) = guard.get_mut(&source_ip) {
+                    // known endpoint, update services and properties
+                    return endpoint.add_services(records, now, &self.rules, &self.services);
+                } else {
+                    // new endpoint
+                    let mut endpoint = self.new_endpoint(source_ip, interface, live, now);
+                    endpoint.add_services(records, now, &self.rules, &self.services);
+                    guard.insert(source_ip, endpoint.clone());
+                    return vec![Event::EndpointDiscovered {
+                        endpoint: Box::new(endpoint),
+                    }];
+                }
+            }
+        }
+
+        vec![]
     }
 }
@@ -0,0 +1,61 @@
+use std::net::IpAddr;
+
+use serde::Serialize;
+
+use crate::mdns::{Endpoint, Service};
+
+/// Something that changed while discovering endpoints, serialized with its snake case name
+/// as the `event` field.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A new endpoint has been found.
+    EndpointDiscovered { endpoint: Box<Endpoint> },
+    /// A known endpoint is exposing a new service.
+    ServiceAdded { address: IpAddr, service: Service },
+    /// A service of a known endpoint has a new property value.
+    PropertyChanged {
+        address: IpAddr,
+        service: String,
+        key: String,
+        value: String,
+    },
+    /// A property value of a service has expired or has been withdrawn.
+    RecordExpired {
+        address: IpAddr,
+        service: String,
+        key: String,
+        value: String,
+    },
+    /// An endpoint has no more live records.
+    EndpointGone { address: IpAddr },
+    /// A host on the network is looking for these names, they're stored as the interests
+    /// of its endpoint.
+    QueryObserved {
+        source: IpAddr,
+        questions: Vec<String>,
+    },
+}
+
+/// An event and the time it happened at, as a unix timestamp in seconds. When reading from a
+/// capture file this is the capture time of the packet.
+#[derive(Clone, Debug, Serialize)]
+pub struct Timestamped {
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+impl Event {
+    /// The address of the endpoint that changed, the querier for observed queries.
+    pub fn endpoint_address(&self) -> IpAddr {
+        match self {
+            Event::EndpointDiscovered { endpoint } => endpoint.address,
+            Event::ServiceAdded { address, .. }
+            | Event::PropertyChanged { address, .. }
+            | Event::RecordExpired { address, .. }
+            | Event::EndpointGone { address } => *address,
+            Event::QueryObserved { source, .. } => *source,
+        }
+    }
+}
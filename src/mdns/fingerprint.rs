@@ -1,56 +1,424 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
 use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::mdns::txt::{TxtRecord, TxtValue};
+use crate::mdns::{Candidate, Endpoint, Fingerprint};
+
+// the default rules, shipped with the binary
+const BUILTIN_RULES: &str = include_str!("fingerprints.json");
 
-use crate::mdns::{Endpoint, Fingerprint};
+#[derive(Clone, Copy)]
+enum Detail {
+    Model,
+    OsVersion,
+    // darwin major version, as in _device-info osxvers=
+    DarwinVersion,
+    Firmware,
+    FriendlyName,
+}
+
+// well known TXT keys carrying device details, by service type
+const DETAILS: &[(&str, &str, Detail)] = &[
+    ("_device-info.", "model", Detail::Model),
+    ("_device-info.", "osxvers", Detail::DarwinVersion),
+    ("_googlecast.", "md", Detail::Model),
+    ("_googlecast.", "fn", Detail::FriendlyName),
+    ("_airplay.", "model", Detail::Model),
+    ("_airplay.", "am", Detail::Model),
+    ("_airplay.", "osvers", Detail::OsVersion),
+    ("_airplay.", "fv", Detail::Firmware),
+    ("_airplay.", "srcvers", Detail::Firmware),
+    ("_raop.", "am", Detail::Model),
+    ("_raop.", "fv", Detail::Firmware),
+    ("_hap.", "md", Detail::Model),
+    ("_ipp.", "ty", Detail::Model),
+    ("_ipps.", "ty", Detail::Model),
+    ("_printer.", "ty", Detail::Model),
+    ("_pdl-datastream.", "ty", Detail::Model),
+];
 
 lazy_static! {
-    static ref CHECKS: Vec<(&'static str, Fingerprint)> = {
-        vec![
-            (
-                "_googlecast.",
-                Fingerprint {
-                    vendor: "google".to_string(),
-                    kind: "chromecast".to_string(),
-                },
-            ),
-            (
-                "_adisk.",
-                Fingerprint {
-                    vendor: "".to_string(),
-                    kind: "disk".to_string(),
-                },
-            ),
-            (
-                "_hue.",
-                Fingerprint {
-                    vendor: "philips".to_string(),
-                    kind: "light".to_string(),
-                },
-            ),
-            (
-                "_device-info.",
-                Fingerprint {
-                    vendor: "apple".to_string(),
-                    kind: "osx".to_string(),
-                },
-            ),
-            (
-                "_apple",
-                Fingerprint {
-                    vendor: "apple".to_string(),
-                    kind: "apple".to_string(),
-                },
-            ),
-        ]
+    // parsed once and copied by every set of rules
+    static ref BUILTIN: Vec<Matcher> =
+        parse(BUILTIN_RULES).expect("could not parse the builtin fingerprinting rules");
+}
+
+/// A fingerprinting rule as it is written in a rules file, every pattern is a regular
+/// expression and all the conditions that are set must match the same service.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Rule {
+    pub vendor: String,
+    pub kind: String,
+    // how much a match of this rule can be trusted, from 0 to 1
+    #[serde(default = "default_confidence")]
+    pub confidence: f32,
+    // service type, such as _googlecast._tcp.local
+    #[serde(default)]
+    pub service: Option<String>,
+    // instance name, such as Living Room._googlecast._tcp.local
+    #[serde(default)]
+    pub instance: Option<String>,
+    // TXT key -> pattern of its value, an empty pattern only requires the key to be present
+    #[serde(default)]
+    pub txt: HashMap<String, String>,
+    // SRV port
+    #[serde(default)]
+    pub port: Option<u16>,
+    // SRV target or name of the endpoint
+    #[serde(default)]
+    pub host: Option<String>,
+}
+
+fn default_confidence() -> f32 {
+    0.5
+}
+
+#[derive(Clone)]
+struct Matcher {
+    vendor: String,
+    kind: String,
+    confidence: f32,
+    service: Option<Regex>,
+    instance: Option<Regex>,
+    txt: Vec<(String, Regex)>,
+    port: Option<u16>,
+    host: Option<Regex>,
+}
+
+// what the rules are matched against, either a service record or a resolved instance
+struct Target<'a> {
+    service: &'a str,
+    instance: Option<&'a str>,
+    txt: &'a TxtRecord,
+    port: Option<u16>,
+    host: Option<&'a str>,
+}
+
+fn compile(pattern: &Option<String>) -> Result<Option<Regex>, String> {
+    match pattern {
+        Some(pattern) => Regex::new(pattern)
+            .map(Some)
+            .map_err(|e| format!("invalid pattern '{}': {}", pattern, e)),
+        None => Ok(None),
+    }
+}
+
+impl Matcher {
+    fn new(rule: Rule) -> Result<Self, String> {
+        if !(0.0..=1.0).contains(&rule.confidence) {
+            return Err(format!(
+                "confidence of {}/{} must be between 0 and 1",
+                rule.vendor, rule.kind
+            ));
+        }
+
+        if rule.service.is_none()
+            && rule.instance.is_none()
+            && rule.txt.is_empty()
+            && rule.port.is_none()
+            && rule.host.is_none()
+        {
+            return Err(format!(
+                "rule {}/{} has no conditions",
+                rule.vendor, rule.kind
+            ));
+        }
+
+        let mut txt = vec![];
+        for (key, pattern) in &rule.txt {
+            let regex = Regex::new(pattern)
+                .map_err(|e| format!("invalid pattern '{}' for {}: {}", pattern, key, e))?;
+            // TXT keys are case insensitive
+            txt.push((key.to_ascii_lowercase(), regex));
+        }
+
+        Ok(Self {
+            service: compile(&rule.service)?,
+            instance: compile(&rule.instance)?,
+            host: compile(&rule.host)?,
+            port: rule.port,
+            txt,
+            vendor: rule.vendor,
+            kind: rule.kind,
+            confidence: rule.confidence,
+        })
+    }
+
+    // returns what matched if all the conditions are met
+    fn evidence(&self, target: &Target, endpoint_name: Option<&str>) -> Option<Vec<String>> {
+        let mut evidence = vec![];
+
+        if let Some(service) = &self.service {
+            if !service.is_match(target.service) {
+                return None;
+            }
+            evidence.push(format!(
+                "service {} ~ /{}/",
+                target.service,
+                service.as_str()
+            ));
+        }
+
+        if let Some(instance) = &self.instance {
+            match target.instance {
+                Some(name) if instance.is_match(name) => {
+                    evidence.push(format!("instance {} ~ /{}/", name, instance.as_str()))
+                }
+                _ => return None,
+            }
+        }
+
+        for (key, pattern) in &self.txt {
+            match target.txt.get(key) {
+                Some(value) if pattern.is_match(&value.to_string()) => {
+                    evidence.push(format!("txt {}={} ~ /{}/", key, value, pattern.as_str()))
+                }
+                _ => return None,
+            }
+        }
+
+        if let Some(port) = self.port {
+            if target.port != Some(port) {
+                return None;
+            }
+            evidence.push(format!("port {} of {}", port, target.service));
+        }
+
+        if let Some(host) = &self.host {
+            let name = [target.host, endpoint_name]
+                .into_iter()
+                .flatten()
+                .find(|name| host.is_match(name))?;
+            evidence.push(format!("host {} ~ /{}/", name, host.as_str()));
+        }
+
+        Some(evidence)
+    }
+}
+
+fn parse(data: &str) -> Result<Vec<Matcher>, String> {
+    let rules: Vec<Rule> = serde_json::from_str(data).map_err(|e| e.to_string())?;
+    rules.into_iter().map(Matcher::new).collect()
+}
+
+/// The fingerprinting rules used by an agent, the builtin ones unless more are loaded.
+#[derive(Clone)]
+pub struct Rules {
+    matchers: Vec<Matcher>,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Self {
+            matchers: BUILTIN.clone(),
+        }
+    }
+}
+
+impl Rules {
+    /// Load extra rules from a JSON file, on equal confidence these win over the ones
+    /// already loaded.
+    pub fn load(&mut self, path: &str) -> Result<usize, String> {
+        let data = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let matchers = parse(&data).map_err(|e| format!("{}: {}", path, e))?;
+        let count = matchers.len();
+
+        self.matchers.splice(0..0, matchers);
+
+        Ok(count)
+    }
+}
+
+// https://en.wikipedia.org/wiki/Darwin_(operating_system)#Release_history
+fn macos_version(darwin: &str) -> Option<String> {
+    let major = darwin.split('.').next()?.parse::<u32>().ok()?;
+    match major {
+        5..=19 => Some(format!("10.{}", major - 4)),
+        20..=24 => Some((major - 9).to_string()),
+        // macOS 26 followed 15
+        _ if major >= 25 => Some((major + 1).to_string()),
+        _ => None,
+    }
+}
+
+// the OS of an Apple device can be told by its model identifier
+fn apple_os(model: &str) -> Option<&'static str> {
+    if model.starts_with("AppleTV") {
+        Some("tvOS")
+    } else if model.starts_with("AudioAccessory") {
+        Some("audioOS")
+    } else if model.starts_with("iPhone") || model.starts_with("iPod") {
+        Some("iOS")
+    } else if model.starts_with("iPad") {
+        Some("iPadOS")
+    } else if model.starts_with("Watch") {
+        Some("watchOS")
+    } else if model.contains("Mac") {
+        Some("macOS")
+    } else {
+        None
+    }
+}
+
+// the instance label of a service, without the MAC prefix used by _raop
+fn instance_label(name: &str) -> Option<String> {
+    if name.starts_with('_') {
+        return None;
+    }
+    let label = name.split("._").next()?;
+    let label = match label.split_once('@') {
+        Some((_, label)) => label,
+        None => label,
     };
+    if label.is_empty() {
+        None
+    } else {
+        Some(label.to_owned())
+    }
 }
 
-pub fn get(endpoint: &Endpoint) -> Option<Fingerprint> {
+// fills the fingerprint with the model, OS, firmware and name found in the TXT records
+fn add_details(endpoint: &Endpoint, fingerprint: &mut Fingerprint) {
+    let services = endpoint
+        .services
+        .values()
+        .sorted_by(|a, b| a.name.cmp(&b.name));
+
+    for service in services {
+        let mut found = false;
+        for (service_type, key, detail) in DETAILS {
+            if !service.name.contains(service_type) {
+                continue;
+            }
+            let value = match service.txt.get(*key) {
+                Some(TxtValue::Text(value)) if !value.is_empty() => value.to_owned(),
+                _ => continue,
+            };
+
+            found = true;
+            // the first value found wins
+            let field = match detail {
+                Detail::Model => &mut fingerprint.model,
+                Detail::OsVersion => &mut fingerprint.os_version,
+                Detail::Firmware => &mut fingerprint.firmware,
+                Detail::FriendlyName => &mut fingerprint.friendly_name,
+                Detail::DarwinVersion => {
+                    if fingerprint.os.is_none() {
+                        fingerprint.os = Some("macOS".to_string());
+                    }
+                    if fingerprint.os_version.is_none() {
+                        fingerprint.os_version = macos_version(&value);
+                    }
+                    continue;
+                }
+            };
+            if field.is_none() {
+                *field = Some(value);
+            }
+        }
+
+        // devices usually announce themselves with their user given name
+        if found && fingerprint.friendly_name.is_none() {
+            fingerprint.friendly_name = instance_label(&service.name);
+        }
+    }
+
+    if fingerprint.os.is_none() && fingerprint.vendor == "apple" {
+        if let Some(model) = &fingerprint.model {
+            fingerprint.os = apple_os(model).map(|os| os.to_string());
+        }
+    }
+}
+
+/// Score every rule against all the services of the endpoint, returns the candidates
+/// sorted by decreasing confidence.
+pub fn candidates(endpoint: &Endpoint, rules: &Rules) -> Vec<Candidate> {
+    let mut targets = vec![];
     for service in endpoint.services.values() {
-        for (name, finger) in &*CHECKS {
-            if service.name.contains(name) {
-                return Some(finger.clone());
+        let srv = service.srv.first();
+        targets.push(Target {
+            service: &service.name,
+            instance: None,
+            txt: &service.txt,
+            port: srv.map(|srv| srv.port),
+            host: srv.map(|srv| srv.target.as_str()),
+        });
+    }
+    for instance in endpoint.instances.values() {
+        targets.push(Target {
+            service: &instance.service_type,
+            instance: Some(&instance.name),
+            txt: &instance.txt,
+            port: instance.port,
+            host: instance.host.as_deref(),
+        });
+    }
+
+    let name = endpoint.name.as_deref();
+    let mut candidates: Vec<Candidate> = vec![];
+
+    for rule in &rules.matchers {
+        let mut evidence = vec![];
+        for target in &targets {
+            if let Some(found) = rule.evidence(target, name) {
+                evidence.extend(found);
             }
         }
+        if evidence.is_empty() {
+            continue;
+        }
+
+        let candidate = match candidates
+            .iter_mut()
+            .find(|c| c.vendor == rule.vendor && c.kind == rule.kind)
+        {
+            Some(candidate) => candidate,
+            None => {
+                candidates.push(Candidate {
+                    vendor: rule.vendor.to_owned(),
+                    kind: rule.kind.to_owned(),
+                    confidence: 0.0,
+                    evidence: vec![],
+                });
+                candidates.last_mut().unwrap()
+            }
+        };
+
+        // every rule that agrees on the same device makes it more likely
+        candidate.confidence = 1.0 - (1.0 - candidate.confidence) * (1.0 - rule.confidence);
+        for item in evidence {
+            if !candidate.evidence.contains(&item) {
+                candidate.evidence.push(item);
+            }
+        }
+    }
+
+    // stable, on equal scores the rule that comes first wins
+    candidates.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+    candidates
+}
+
+/// Fingerprint an endpoint from its best candidate and the details in its TXT records.
+pub fn get(endpoint: &Endpoint, candidates: &[Candidate]) -> Option<Fingerprint> {
+    let mut fingerprint = match candidates.first() {
+        Some(best) => Fingerprint {
+            vendor: best.vendor.to_owned(),
+            kind: best.kind.to_owned(),
+            confidence: best.confidence,
+            ..Default::default()
+        },
+        None => Fingerprint::default(),
+    };
+
+    add_details(endpoint, &mut fingerprint);
+
+    if fingerprint.vendor.is_empty() && fingerprint.kind.is_empty() && fingerprint.model.is_none() {
+        None
+    } else {
+        Some(fingerprint)
     }
-    None
 }
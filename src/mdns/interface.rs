@@ -0,0 +1,115 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+#[derive(Clone, Debug)]
+pub struct Interface {
+    pub name: String,
+    // taken from the scope of the link local address, needed for IPv6 multicast
+    pub index: Option<u32>,
+    pub ipv4: Vec<(Ipv4Addr, Ipv4Addr)>,
+    pub ipv6: Vec<(Ipv6Addr, Ipv6Addr)>,
+}
+
+impl Interface {
+    fn from_system(iface: &interfaces::Interface) -> Self {
+        let mut index = None;
+        let mut ipv4 = vec![];
+        let mut ipv6 = vec![];
+
+        for addr in iface.addresses.iter() {
+            match (addr.addr, addr.mask) {
+                (Some(SocketAddr::V4(ip)), mask) => {
+                    let mask = match mask {
+                        Some(SocketAddr::V4(mask)) => *mask.ip(),
+                        _ => Ipv4Addr::BROADCAST,
+                    };
+                    ipv4.push((*ip.ip(), mask));
+                }
+                (Some(SocketAddr::V6(ip)), mask) => {
+                    let mask = match mask {
+                        Some(SocketAddr::V6(mask)) => *mask.ip(),
+                        _ => Ipv6Addr::from(u128::MAX),
+                    };
+                    if ip.scope_id() != 0 && index.is_none() {
+                        index = Some(ip.scope_id());
+                    }
+                    ipv6.push((*ip.ip(), mask));
+                }
+                _ => {}
+            }
+        }
+
+        Self {
+            name: iface.name.to_owned(),
+            index,
+            ipv4,
+            ipv6,
+        }
+    }
+
+    // returns all the interfaces that are up and are not loopback
+    pub fn get_all() -> Result<Vec<Interface>, String> {
+        Ok(interfaces::Interface::get_all()
+            .map_err(|e| e.to_string())?
+            .iter()
+            .filter(|iface| iface.is_up() && !iface.is_loopback())
+            .map(Self::from_system)
+            .collect())
+    }
+
+    // find an interface by name or by one of its addresses
+    pub fn find(name_or_address: &str) -> Result<Interface, String> {
+        let address = name_or_address.parse::<IpAddr>().ok();
+
+        for iface in Self::get_all()? {
+            if iface.name == name_or_address
+                || matches!(address, Some(address) if iface.has_address(&address))
+            {
+                return Ok(iface);
+            }
+        }
+
+        Err(format!("interface {} not found", name_or_address))
+    }
+
+    // returns the name of the local interface that is on the same network of this address
+    pub fn find_for(address: &SocketAddr) -> Option<String> {
+        Self::get_all()
+            .ok()?
+            .into_iter()
+            .find(|iface| iface.contains(address))
+            .map(|iface| iface.name)
+    }
+
+    pub fn has_address(&self, address: &IpAddr) -> bool {
+        match address {
+            IpAddr::V4(address) => self.ipv4.iter().any(|(ip, _)| ip == address),
+            IpAddr::V6(address) => self.ipv6.iter().any(|(ip, _)| ip == address),
+        }
+    }
+
+    pub fn first_ipv4(&self) -> Option<Ipv4Addr> {
+        self.ipv4.first().map(|(ip, _)| *ip)
+    }
+
+    // returns true if the address is on the same network of this interface
+    pub fn contains(&self, address: &SocketAddr) -> bool {
+        match address {
+            SocketAddr::V4(address) => {
+                let address = u32::from(*address.ip());
+                self.ipv4.iter().any(|(ip, mask)| {
+                    u32::from(*ip) & u32::from(*mask) == address & u32::from(*mask)
+                })
+            }
+            SocketAddr::V6(address) => {
+                // link local addresses carry the index of the interface they've been received from
+                if address.scope_id() != 0 {
+                    return self.index == Some(address.scope_id());
+                }
+                let address = u128::from(*address.ip());
+                self.ipv6.iter().any(|(ip, mask)| {
+                    u128::from(*ip) & u128::from(*mask) == address & u128::from(*mask)
+                })
+            }
+        }
+    }
+}
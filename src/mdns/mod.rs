@@ -1,27 +1,141 @@
 use std::collections::HashMap;
 use std::net::{IpAddr, SocketAddr};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use dns_lookup::getnameinfo;
-use dns_parser::RData;
-use serde::{Deserialize, Serialize};
+use dns_parser::{Question, RData, ResourceRecord};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use event::Event;
+use txt::TxtRecord;
 
 pub mod discovery;
+pub mod event;
+pub mod interface;
+pub mod pcap;
+pub mod services;
+pub mod transport;
+pub mod txt;
+
+pub mod fingerprint;
+
+// unix timestamp in seconds
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PropertyValue {
+    pub value: String,
+    // metadata of the record this value has been parsed from
+    pub record_type: String,
+    pub class: String,
+    pub ttl: u32,
+    pub cache_flush: bool,
+    // unix timestamps
+    pub first_seen: u64,
+    pub last_seen: u64,
+    // after this the value is no longer valid
+    pub expires_at: u64,
+}
+
+impl PropertyValue {
+    pub fn from_record(rec: &ResourceRecord, value: String, now: u64) -> Self {
+        Self {
+            value,
+            record_type: record_type(&rec.data),
+            class: format!("{:?}", rec.cls),
+            ttl: rec.ttl,
+            cache_flush: rec.multicast_unique,
+            first_seen: now,
+            last_seen: now,
+            expires_at: now + rec.ttl as u64,
+        }
+    }
 
-mod fingerprint;
+    // a value saved by an older version, without the metadata of its record
+    fn saved(value: String) -> Self {
+        Self {
+            value,
+            record_type: String::new(),
+            class: String::new(),
+            ttl: 0,
+            cache_flush: false,
+            first_seen: 0,
+            last_seen: 0,
+            expires_at: 0,
+        }
+    }
+}
+
+// older versions saved the values as plain strings
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SavedValue {
+    Value(PropertyValue),
+    Plain(String),
+}
+
+fn deserialize_values<'de, D>(deserializer: D) -> Result<HashMap<String, PropertyValues>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let saved = HashMap::<String, Vec<SavedValue>>::deserialize(deserializer)?;
+    Ok(saved
+        .into_iter()
+        .map(|(key, values)| {
+            let values = values
+                .into_iter()
+                .map(|value| match value {
+                    SavedValue::Value(value) => value,
+                    SavedValue::Plain(value) => PropertyValue::saved(value),
+                })
+                .collect();
+            (key, values)
+        })
+        .collect())
+}
+
+fn record_type(data: &RData) -> String {
+    let name = match data {
+        RData::A(_) => "A",
+        RData::AAAA(_) => "AAAA",
+        RData::CNAME(_) => "CNAME",
+        RData::MX(_) => "MX",
+        RData::NS(_) => "NS",
+        RData::PTR(_) => "PTR",
+        RData::SOA(_) => "SOA",
+        RData::SRV(_) => "SRV",
+        RData::TXT(_) => "TXT",
+        RData::Unknown(typ, _) => return format!("{:?}", typ),
+    };
+    name.to_owned()
+}
 
-pub type PropertyValues = Vec<String>;
+pub type PropertyValues = Vec<PropertyValue>;
 
-#[derive(Serialize, Deserialize, Clone)]
-pub struct Properties(pub HashMap<String, PropertyValues>);
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct Properties(
+    #[serde(deserialize_with = "deserialize_values")] pub HashMap<String, PropertyValues>,
+);
 
 impl Properties {
     pub fn new() -> Self {
         Self(HashMap::new())
     }
 
-    pub fn add(&mut self, key: &str, value: String) {
+    // adds the value or refreshes it if already present
+    pub fn add(&mut self, key: &str, value: PropertyValue) {
         if let Some(ref mut prop) = self.0.get_mut(key) {
-            if !prop.contains(&value) {
+            if let Some(existing) = prop.iter_mut().find(|v| v.value == value.value) {
+                existing.ttl = value.ttl;
+                existing.cache_flush = value.cache_flush;
+                existing.last_seen = value.last_seen;
+                existing.expires_at = value.expires_at;
+            } else {
                 prop.push(value);
             }
         } else {
@@ -29,118 +143,302 @@ impl Properties {
         }
     }
 
-    pub fn merge(&mut self, props: &Properties) {
+    // returns the (key, value) pairs that were not already present
+    pub fn merge(&mut self, props: &Properties) -> Vec<(String, String)> {
+        let mut added = vec![];
         for (key, values) in &props.0 {
             for value in values {
-                self.add(key, value.to_string());
+                if !self.contains(key, &value.value) {
+                    added.push((key.to_string(), value.value.to_string()));
+                }
+                self.add(key, value.clone());
+            }
+        }
+        added
+    }
+
+    // returns the (key, value) pairs that have been removed
+    pub fn remove(&mut self, props: &Properties) -> Vec<(String, String)> {
+        let mut removed = vec![];
+        for (key, values) in &props.0 {
+            for value in values {
+                if self.contains(key, &value.value) {
+                    removed.push((key.to_string(), value.value.to_string()));
+                }
+            }
+        }
+        self.retain(|key, value| !props.contains(key, &value.value));
+        removed
+    }
+
+    // removes the values that expired before now and returns them as (key, value) pairs
+    pub fn expire(&mut self, now: u64) -> Vec<(String, String)> {
+        let mut expired = vec![];
+        for (key, values) in &self.0 {
+            for value in values {
+                if value.expires_at <= now {
+                    expired.push((key.to_string(), value.value.to_string()));
+                }
             }
         }
+        self.retain(|_, value| value.expires_at > now);
+        expired
+    }
+
+    fn retain(&mut self, f: impl Fn(&str, &PropertyValue) -> bool) {
+        for (key, values) in self.0.iter_mut() {
+            values.retain(|value| f(key, value));
+        }
+        self.0.retain(|_, values| !values.is_empty());
     }
 
     pub fn get(&self, key: &str) -> Option<&PropertyValues> {
         self.0.get(key)
     }
 
+    pub fn contains(&self, key: &str, value: &str) -> bool {
+        matches!(self.get(key), Some(values) if values.iter().any(|v| v.value == value))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
     pub fn has_ip(&self) -> bool {
         self.get("ipv4").is_some() || self.get("ipv6").is_some()
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Srv {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub target: String,
+    // resolved through the A/AAAA records of the target
+    pub addresses: Vec<IpAddr>,
+}
+
+impl Srv {
+    // the value of the server property for this record
+    pub fn server(&self) -> String {
+        format!("{}:{}", self.target, self.port)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Service {
     pub name: String,
     pub description: Option<String>,
     pub properties: Properties,
+    // parsed from the last TXT record
+    #[serde(default)]
+    pub txt: TxtRecord,
+    #[serde(default)]
+    pub srv: Vec<Srv>,
+    // unix timestamps
+    #[serde(default)]
+    pub first_seen: u64,
+    #[serde(default)]
+    pub last_seen: u64,
+}
+
+// a service instance resolved through its PTR, SRV, TXT and A/AAAA records
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Instance {
+    pub name: String,
+    pub service_type: String,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub addresses: Vec<IpAddr>,
+    pub txt: TxtRecord,
+}
+
+// a question asked by an endpoint
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Interest {
+    pub name: String,
+    pub query_type: String,
+    // the QU bit, the endpoint asked for a unicast response
+    pub unicast_response: bool,
+    // records the endpoint already knows, sent along with the question
+    pub known_answers: Vec<String>,
+    // how many times it has been asked
+    pub count: u64,
+    // unix timestamps
+    pub first_seen: u64,
+    pub last_seen: u64,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct Fingerprint {
     pub vendor: String,
     pub kind: String,
+    // of the best candidate, from 0 to 1
+    #[serde(default)]
+    pub confidence: f32,
+    // taken from well known TXT keys
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub os: Option<String>,
+    #[serde(default)]
+    pub os_version: Option<String>,
+    #[serde(default)]
+    pub firmware: Option<String>,
+    #[serde(default)]
+    pub friendly_name: Option<String>,
+}
+
+// a possible fingerprint with the rules evidence that supports it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Candidate {
+    pub vendor: String,
+    pub kind: String,
+    pub confidence: f32,
+    pub evidence: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Endpoint {
     pub name: Option<String>,
     pub address: IpAddr,
+    #[serde(default)]
+    pub interface: Option<String>,
     pub local: bool,
+    // false once all the records of this endpoint expired or have been withdrawn
+    #[serde(default)]
+    pub online: bool,
+    // restored from a previous scan and not seen since
+    #[serde(default)]
+    pub stale: bool,
     pub services: HashMap<String, Service>,
+    #[serde(default)]
+    pub instances: HashMap<String, Instance>,
+    // what this endpoint has been asking for
+    #[serde(default)]
+    pub interests: Vec<Interest>,
     pub fingerprint: Option<Fingerprint>,
+    // every fingerprint that matched, best first
+    #[serde(default)]
+    pub candidates: Vec<Candidate>,
+    // unix timestamps
+    #[serde(default)]
+    pub first_seen: u64,
+    #[serde(default)]
+    pub last_seen: u64,
 }
 
 impl Endpoint {
-    pub fn with_services<'b>(
-        address: SocketAddr,
-        records: impl Iterator<Item = &'b dns_parser::ResourceRecord<'b>>,
-    ) -> Endpoint {
-        let name = match getnameinfo(&address, 0) {
-            Ok((name, _)) => {
-                if name == address.ip().to_string() {
-                    None
-                } else {
-                    Some(name)
-                }
+    pub fn new(address: IpAddr, interface: Option<String>, now: u64) -> Endpoint {
+        Endpoint {
+            name: None,
+            local: false,
+            online: true,
+            stale: false,
+            address,
+            interface,
+            services: HashMap::new(),
+            instances: HashMap::new(),
+            interests: vec![],
+            fingerprint: None,
+            candidates: vec![],
+            first_seen: now,
+            last_seen: now,
+        }
+    }
+
+    // resolves the name of the endpoint and checks if it's one of our addresses
+    pub fn lookup(&mut self) {
+        let address = SocketAddr::new(self.address, 0);
+        if let Ok((name, _)) = getnameinfo(&address, 0) {
+            if name != self.address.to_string() {
+                self.name = Some(name);
             }
-            Err(_) => None,
-        };
+        }
 
-        let mut local = false;
         for iface in interfaces::Interface::get_all().expect("could not get network interfaces") {
             for addr in iface.addresses.iter() {
                 if let Some(ip) = addr.addr {
-                    if ip.ip() == address.ip() {
-                        local = true;
+                    if ip.ip() == self.address {
+                        self.local = true;
                         break;
                     }
                 }
             }
         }
-
-        let mut endpoint = Endpoint {
-            name,
-            local,
-            address: address.ip(),
-            services: HashMap::new(),
-            fingerprint: None,
-        };
-        endpoint.add_services(records);
-        endpoint
     }
 
-    fn add_ip_property(properties: &mut Properties, ip: String) {
-        properties.add(if ip.contains(':') { "ipv6" } else { "ipv4" }, ip);
+    fn add_ip_property(properties: &mut Properties, value: PropertyValue) {
+        let key = if value.value.contains(':') {
+            "ipv6"
+        } else {
+            "ipv4"
+        };
+        properties.add(key, value);
     }
 
-    fn parse_properties<'b>(data: &'b RData<'b>) -> Properties {
+    fn parse_properties(rec: &ResourceRecord, now: u64) -> Properties {
         let mut properties = Properties::new();
+        let value = |value: String| PropertyValue::from_record(rec, value, now);
 
-        match data {
-            RData::AAAA(ip) => Self::add_ip_property(&mut properties, ip.0.to_string()),
-            RData::A(ip) => Self::add_ip_property(&mut properties, ip.0.to_string()),
-            RData::PTR(name) => properties.add("name", name.0.to_string()),
-            RData::SRV(server) => {
-                properties.add("server", format!("{}:{}", server.target, server.port))
+        match &rec.data {
+            RData::AAAA(ip) => Self::add_ip_property(&mut properties, value(ip.0.to_string())),
+            RData::A(ip) => Self::add_ip_property(&mut properties, value(ip.0.to_string())),
+            RData::PTR(name) => properties.add("name", value(name.0.to_string())),
+            RData::SRV(server) => properties.add(
+                "server",
+                value(format!("{}:{}", server.target, server.port)),
+            ),
+            RData::Unknown(typ, raw) => {
+                properties.add(&format!("{:?}", typ), value(format!("{:?}", raw)))
             }
-            RData::Unknown(typ, raw) => properties.add(&format!("{:?}", typ), format!("{:?}", raw)),
             RData::TXT(txt) => {
                 for chunk in txt.iter() {
                     if let Ok(str) = String::from_utf8(chunk.to_vec()) {
-                        properties.add("text", str);
+                        properties.add("text", value(str));
                     } else if !chunk.is_empty() {
-                        properties.add("text", format!("{:?}", chunk));
+                        properties.add("text", value(txt::to_hex(chunk)));
                     }
                 }
             }
-            _ => properties.add("???", format!("{:?}", data)),
+            data => properties.add("???", value(format!("{:?}", data))),
         }
 
         properties
     }
 
+    fn parse_srv(rec: &ResourceRecord) -> Option<Srv> {
+        match &rec.data {
+            RData::SRV(data) => Some(Srv {
+                priority: data.priority,
+                weight: data.weight,
+                port: data.port,
+                target: data.target.to_string(),
+                addresses: vec![],
+            }),
+            _ => None,
+        }
+    }
+
+    fn parse_txt(rec: &ResourceRecord) -> Option<TxtRecord> {
+        match &rec.data {
+            RData::TXT(data) => Some(txt::parse(data.iter())),
+            _ => None,
+        }
+    }
+
+    // returns the events describing what changed
     pub fn add_services<'b>(
         &mut self,
         records: impl Iterator<Item = &'b dns_parser::ResourceRecord<'b>>,
-    ) {
+        now: u64,
+        rules: &fingerprint::Rules,
+        services: &services::Services,
+    ) -> Vec<Event> {
+        let mut events = vec![];
+        let mut live_records = false;
+        let mut withdrawn = false;
         // for every answer
         for rec in records {
             // println!("{:?} - {:?}", self.address, rec);
@@ -148,7 +446,26 @@ impl Endpoint {
             let svc_name = rec.name.to_string();
             if svc_name != discovery::DNS_ENUMERATION_SERVICE_NAME {
                 // parse record data into properties
-                let properties = Self::parse_properties(&rec.data);
+                let properties = Self::parse_properties(rec, now);
+
+                // goodbye packet, the record has been withdrawn
+                if rec.ttl == 0 {
+                    if let Some(service) = self.services.get_mut(&svc_name) {
+                        for (key, value) in service.properties.remove(&properties) {
+                            withdrawn = true;
+                            events.push(Event::RecordExpired {
+                                address: self.address,
+                                service: svc_name.to_owned(),
+                                key,
+                                value,
+                            });
+                        }
+                    }
+                    continue;
+                }
+
+                live_records = true;
+                self.last_seen = now;
                 // if this endpoint still has no name, check if this record can be used for it
                 if self.name.is_none() && properties.has_ip() {
                     self.name = Some(svc_name.clone());
@@ -156,26 +473,260 @@ impl Endpoint {
 
                 if let Some(service) = self.services.get_mut(&svc_name) {
                     // known service, update properties
-                    service.properties.merge(&properties);
+                    service.last_seen = now;
+                    if let Some(txt) = Self::parse_txt(rec) {
+                        service.txt = txt;
+                    }
+                    if let Some(srv) = Self::parse_srv(rec) {
+                        service.srv.retain(|known| known.server() != srv.server());
+                        service.srv.push(srv);
+                    }
+                    for (key, value) in service.properties.merge(&properties) {
+                        events.push(Event::PropertyChanged {
+                            address: self.address,
+                            service: svc_name.to_owned(),
+                            key,
+                            value,
+                        });
+                    }
                 } else {
                     // new service
                     let name = svc_name.to_owned();
-                    let description = discovery::get_service_description(&name);
-                    self.services.insert(
-                        name.to_owned(),
-                        Service {
-                            name,
-                            description,
-                            properties,
-                        },
-                    );
+                    let description = services.description(&name);
+                    let service = Service {
+                        name,
+                        description,
+                        properties,
+                        txt: Self::parse_txt(rec).unwrap_or_default(),
+                        srv: Self::parse_srv(rec).into_iter().collect(),
+                        first_seen: now,
+                        last_seen: now,
+                    };
+                    events.push(Event::ServiceAdded {
+                        address: self.address,
+                        service: service.clone(),
+                    });
+                    self.services.insert(svc_name.to_owned(), service);
                 }
+            }
+        }
+
+        // what has been withdrawn is dropped before resolving what's left, answers without
+        // records of this endpoint (such as the enumeration) don't make it go offline
+        let back = live_records && (!self.online || self.stale);
+        let gone = if withdrawn {
+            self.update_online()
+        } else {
+            None
+        };
+
+        if live_records || withdrawn {
+            self.refresh(rules);
+        }
+
+        if back {
+            // the endpoint is back
+            self.online = true;
+            self.stale = false;
+            events.push(Event::EndpointDiscovered {
+                endpoint: Box::new(self.clone()),
+            });
+        }
+        events.extend(gone);
+
+        events
+    }
 
-                // attempt fingerprinting
-                if self.fingerprint.is_none() {
-                    self.fingerprint = fingerprint::get(self);
+    // records the questions of a query sent by this endpoint and the known answers it included,
+    // returns the events describing what changed
+    pub fn add_interests(
+        &mut self,
+        questions: &[Question],
+        known_answers: &[ResourceRecord],
+        now: u64,
+    ) -> Vec<Event> {
+        let mut events = vec![];
+        self.last_seen = now;
+
+        for question in questions {
+            let name = question.qname.to_string();
+            let query_type = format!("{:?}", question.qtype);
+            let answers: Vec<String> = known_answers
+                .iter()
+                .filter(|rec| rec.name.to_string() == name)
+                .flat_map(|rec| Self::parse_properties(rec, now).0.into_values().flatten())
+                .map(|value| value.value)
+                .collect();
+
+            match self
+                .interests
+                .iter_mut()
+                .find(|interest| interest.name == name && interest.query_type == query_type)
+            {
+                Some(interest) => {
+                    interest.count += 1;
+                    interest.last_seen = now;
+                    interest.unicast_response = question.prefer_unicast;
+                    for answer in answers {
+                        if !interest.known_answers.contains(&answer) {
+                            interest.known_answers.push(answer);
+                        }
+                    }
                 }
+                None => self.interests.push(Interest {
+                    name,
+                    query_type,
+                    unicast_response: question.prefer_unicast,
+                    known_answers: answers,
+                    count: 1,
+                    first_seen: now,
+                    last_seen: now,
+                }),
+            }
+        }
+
+        if self.stale {
+            // the endpoint of a previous scan is back
+            self.stale = false;
+            events.push(Event::EndpointDiscovered {
+                endpoint: Box::new(self.clone()),
+            });
+        }
+
+        events
+    }
+
+    // removes the values that expired before now, returns the events describing what changed
+    pub fn expire(&mut self, now: u64, rules: &fingerprint::Rules) -> Vec<Event> {
+        let mut events = vec![];
+        // the records of a previous scan are kept until the endpoint is seen again
+        if self.stale {
+            return events;
+        }
+
+        for service in self.services.values_mut() {
+            for (key, value) in service.properties.expire(now) {
+                events.push(Event::RecordExpired {
+                    address: self.address,
+                    service: service.name.to_owned(),
+                    key,
+                    value,
+                });
             }
         }
+
+        if !events.is_empty() {
+            events.extend(self.update_online());
+            self.refresh(rules);
+        }
+
+        events
+    }
+
+    fn values_of(&self, name: &str, key: &str) -> Vec<String> {
+        match self
+            .services
+            .get(name)
+            .and_then(|svc| svc.properties.get(key))
+        {
+            Some(values) => values.iter().map(|v| v.value.to_owned()).collect(),
+            None => vec![],
+        }
+    }
+
+    fn addresses_of(&self, host: &str) -> Vec<IpAddr> {
+        let mut addresses = vec![];
+        for key in ["ipv4", "ipv6"] {
+            addresses.extend(
+                self.values_of(host, key)
+                    .iter()
+                    .filter_map(|ip| ip.parse::<IpAddr>().ok()),
+            );
+        }
+        addresses
+    }
+
+    // rebuilds the instances and fingerprints the endpoint again from the records left
+    fn refresh(&mut self, rules: &fingerprint::Rules) {
+        self.resolve();
+        self.candidates = fingerprint::candidates(self, rules);
+        self.fingerprint = fingerprint::get(self, &self.candidates);
+    }
+
+    // resolves the SRV targets to their addresses and builds the instances from the
+    // records of the service types, which point to the instance names, which point
+    // to their SRV and TXT records
+    fn resolve(&mut self) {
+        let mut resolved = HashMap::new();
+        for service in self.services.values() {
+            for srv in &service.srv {
+                resolved
+                    .entry(srv.target.to_owned())
+                    .or_insert_with(|| self.addresses_of(&srv.target));
+            }
+        }
+
+        for service in self.services.values_mut() {
+            for srv in service.srv.iter_mut() {
+                srv.addresses = resolved.get(&srv.target).cloned().unwrap_or_default();
+            }
+        }
+
+        let mut instances = HashMap::new();
+
+        for service in self.services.values() {
+            // only service types such as _http._tcp.local
+            if !service.name.starts_with('_') {
+                continue;
+            }
+
+            for name in self.values_of(&service.name, "name") {
+                let (srv, txt) = match self.services.get(&name) {
+                    Some(svc) => (svc.srv.first().cloned(), svc.txt.clone()),
+                    None => (None, TxtRecord::new()),
+                };
+
+                instances.insert(
+                    name.to_owned(),
+                    Instance {
+                        name,
+                        service_type: service.name.to_owned(),
+                        host: srv.as_ref().map(|srv| srv.target.to_owned()),
+                        port: srv.as_ref().map(|srv| srv.port),
+                        addresses: srv.map(|srv| srv.addresses).unwrap_or_default(),
+                        txt,
+                    },
+                );
+            }
+        }
+
+        self.instances = instances;
+    }
+
+    // removes the services without values and marks the endpoint offline if none is left
+    fn update_online(&mut self) -> Option<Event> {
+        self.services
+            .retain(|_, service| !service.properties.is_empty());
+
+        // drop the parsed records that are gone
+        for service in self.services.values_mut() {
+            if service.properties.get("text").is_none() {
+                service.txt.clear();
+            }
+
+            let properties = &service.properties;
+            service
+                .srv
+                .retain(|srv| properties.contains("server", &srv.server()));
+        }
+
+        if self.online && self.services.is_empty() {
+            self.online = false;
+            Some(Event::EndpointGone {
+                address: self.address,
+            })
+        } else {
+            None
+        }
     }
 }
@@ -0,0 +1,744 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::mdns::discovery::MULTICAST_PORT;
+use crate::mdns::interface::Interface;
+
+// https://www.tcpdump.org/manpages/pcap-savefile.5.txt
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b23c4d;
+// https://www.ietf.org/archive/id/draft-ietf-opsawg-pcapng-01.html
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 1;
+const PCAPNG_PACKET: u32 = 2;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+const PCAPNG_OPTION_END: u16 = 0;
+const PCAPNG_OPTION_SHB_USERAPPL: u16 = 4;
+const PCAPNG_OPTION_IF_NAME: u16 = 2;
+const PCAPNG_OPTION_IF_DESCRIPTION: u16 = 3;
+const PCAPNG_OPTION_IF_IPV4ADDR: u16 = 4;
+const PCAPNG_OPTION_IF_IPV6ADDR: u16 = 5;
+const PCAPNG_OPTION_IF_TSRESOL: u16 = 9;
+const PCAPNG_OPTION_EPB_FLAGS: u16 = 2;
+// blocks larger than this are surely corrupted
+const MAX_BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
+// https://www.tcpdump.org/linktypes.html
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW_OLD: u32 = 12;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+
+const IPPROTO_UDP: u8 = 17;
+
+/// An mDNS packet read from a capture file.
+#[derive(Clone, Debug)]
+pub struct Captured {
+    // unix timestamp of the capture, in seconds
+    pub timestamp: u64,
+    // as recorded in the pcapng interface description, if any
+    pub interface: Option<String>,
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    // the DNS message
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Copy)]
+struct Endian {
+    big: bool,
+}
+
+impl Endian {
+    fn u16(&self, data: &[u8]) -> u16 {
+        let bytes = [data[0], data[1]];
+        if self.big {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        }
+    }
+
+    fn u32(&self, data: &[u8]) -> u32 {
+        let bytes = [data[0], data[1], data[2], data[3]];
+        if self.big {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+}
+
+struct CaptureInterface {
+    linktype: u32,
+    name: Option<String>,
+    // timestamp units per second
+    resolution: u64,
+}
+
+enum Format {
+    Pcap {
+        endian: Endian,
+        linktype: u32,
+    },
+    PcapNg {
+        endian: Endian,
+        interfaces: Vec<CaptureInterface>,
+    },
+}
+
+// a link layer frame
+struct Frame {
+    timestamp: u64,
+    linktype: u32,
+    interface: Option<String>,
+    data: Vec<u8>,
+}
+
+/// Reads the mDNS packets of a pcap or pcapng file.
+pub struct Reader {
+    file: BufReader<File>,
+    format: Format,
+    // simple packet blocks have no timestamp, the last one is used
+    last_timestamp: u64,
+}
+
+impl Reader {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut file = BufReader::new(file);
+
+        let mut magic = [0u8; 4];
+        file.read_exact(&mut magic)
+            .map_err(|e| format!("{}: {}", path, e))?;
+
+        if u32::from_be_bytes(magic) == PCAPNG_SECTION_HEADER {
+            let mut reader = Self {
+                file,
+                format: Format::PcapNg {
+                    endian: Endian { big: true },
+                    interfaces: vec![],
+                },
+                last_timestamp: 0,
+            };
+            // sets the byte order of the section
+            reader
+                .read_section_header()
+                .map_err(|e| format!("{}: {}", path, e))?;
+            return Ok(reader);
+        }
+
+        // sub second precision is not needed, so nanoseconds files are read the same way
+        let endian = match (u32::from_be_bytes(magic), u32::from_le_bytes(magic)) {
+            (PCAP_MAGIC_MICROS | PCAP_MAGIC_NANOS, _) => Endian { big: true },
+            (_, PCAP_MAGIC_MICROS | PCAP_MAGIC_NANOS) => Endian { big: false },
+            _ => return Err(format!("{}: not a pcap or pcapng file", path)),
+        };
+
+        // version, thiszone, sigfigs, snaplen and network
+        let mut header = [0u8; 20];
+        file.read_exact(&mut header)
+            .map_err(|e| format!("{}: {}", path, e))?;
+
+        Ok(Self {
+            file,
+            format: Format::Pcap {
+                endian,
+                linktype: endian.u32(&header[16..]),
+            },
+            last_timestamp: 0,
+        })
+    }
+
+    // returns false at the end of the file
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<bool, String> {
+        match self.file.read_exact(buf) {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn read_vec(&mut self, size: usize) -> Result<Vec<u8>, String> {
+        if size > MAX_BLOCK_SIZE {
+            return Err(format!("invalid block size {}", size));
+        }
+        let mut data = vec![0u8; size];
+        if !self.read_exact(&mut data)? {
+            return Err("truncated file".to_string());
+        }
+        Ok(data)
+    }
+
+    fn next_frame(&mut self) -> Result<Option<Frame>, String> {
+        match self.format {
+            Format::Pcap { endian, linktype } => {
+                // ts_sec, ts_usec, incl_len and orig_len
+                let mut header = [0u8; 16];
+                if !self.read_exact(&mut header)? {
+                    return Ok(None);
+                }
+                let timestamp = endian.u32(&header[0..]) as u64;
+                let size = endian.u32(&header[8..]) as usize;
+                let data = self.read_vec(size)?;
+
+                Ok(Some(Frame {
+                    timestamp,
+                    linktype,
+                    interface: None,
+                    data,
+                }))
+            }
+            Format::PcapNg { .. } => self.next_block(),
+        }
+    }
+
+    fn read_section_header(&mut self) -> Result<(), String> {
+        // block total length and byte order magic
+        let mut header = [0u8; 8];
+        if !self.read_exact(&mut header)? {
+            return Err("truncated section header".to_string());
+        }
+
+        let endian = match (
+            u32::from_be_bytes([header[4], header[5], header[6], header[7]]),
+            u32::from_le_bytes([header[4], header[5], header[6], header[7]]),
+        ) {
+            (PCAPNG_BYTE_ORDER_MAGIC, _) => Endian { big: true },
+            (_, PCAPNG_BYTE_ORDER_MAGIC) => Endian { big: false },
+            _ => return Err("invalid pcapng byte order magic".to_string()),
+        };
+
+        let size = endian.u32(&header[0..]) as usize;
+        if size < 12 {
+            return Err(format!("invalid section header size {}", size));
+        }
+        // version, section length, options and trailing length
+        self.read_vec(size - 12)?;
+
+        // interface ids are scoped to their section
+        self.format = Format::PcapNg {
+            endian,
+            interfaces: vec![],
+        };
+
+        Ok(())
+    }
+
+    fn next_block(&mut self) -> Result<Option<Frame>, String> {
+        loop {
+            let mut header = [0u8; 4];
+            if !self.read_exact(&mut header)? {
+                return Ok(None);
+            }
+
+            // the section header can switch the byte order
+            if u32::from_be_bytes(header) == PCAPNG_SECTION_HEADER {
+                self.read_section_header()?;
+                continue;
+            }
+
+            let endian = match &self.format {
+                Format::PcapNg { endian, .. } => *endian,
+                Format::Pcap { .. } => unreachable!(),
+            };
+
+            let block_type = endian.u32(&header);
+            let mut size = [0u8; 4];
+            if !self.read_exact(&mut size)? {
+                return Err("truncated block".to_string());
+            }
+            let size = endian.u32(&size) as usize;
+            if size < 12 || size & 3 != 0 {
+                return Err(format!("invalid block size {}", size));
+            }
+            // body and trailing length
+            let body = self.read_vec(size - 8)?;
+            let body = &body[..body.len() - 4];
+
+            match block_type {
+                PCAPNG_INTERFACE_DESCRIPTION => self.add_interface(endian, body)?,
+                PCAPNG_ENHANCED_PACKET if body.len() >= 20 => {
+                    let id = endian.u32(&body[0..]) as usize;
+                    let timestamp =
+                        (endian.u32(&body[4..]) as u64) << 32 | endian.u32(&body[8..]) as u64;
+                    let size = endian.u32(&body[12..]) as usize;
+                    return self.frame(id, timestamp, &body[20..], size).map(Some);
+                }
+                PCAPNG_PACKET if body.len() >= 20 => {
+                    let id = endian.u16(&body[0..]) as usize;
+                    let timestamp =
+                        (endian.u32(&body[4..]) as u64) << 32 | endian.u32(&body[8..]) as u64;
+                    let size = endian.u32(&body[12..]) as usize;
+                    return self.frame(id, timestamp, &body[20..], size).map(Some);
+                }
+                PCAPNG_SIMPLE_PACKET if body.len() >= 4 => {
+                    let data = body[4..].to_vec();
+                    let (linktype, interface) = match &self.format {
+                        Format::PcapNg { interfaces, .. } => match interfaces.first() {
+                            Some(iface) => (iface.linktype, iface.name.clone()),
+                            None => return Err("packet before interface description".to_string()),
+                        },
+                        Format::Pcap { .. } => unreachable!(),
+                    };
+                    return Ok(Some(Frame {
+                        timestamp: self.last_timestamp,
+                        linktype,
+                        interface,
+                        data,
+                    }));
+                }
+                // statistics, name resolution and custom blocks
+                _ => {}
+            }
+        }
+    }
+
+    fn add_interface(&mut self, endian: Endian, body: &[u8]) -> Result<(), String> {
+        if body.len() < 8 {
+            return Err("invalid interface description".to_string());
+        }
+
+        let mut iface = CaptureInterface {
+            linktype: endian.u16(&body[0..]) as u32,
+            name: None,
+            resolution: 1_000_000,
+        };
+
+        let mut options = &body[8..];
+        while options.len() >= 4 {
+            let code = endian.u16(&options[0..]);
+            let size = endian.u16(&options[2..]) as usize;
+            let value = match options.get(4..4 + size) {
+                Some(value) => value,
+                None => break,
+            };
+
+            match code {
+                PCAPNG_OPTION_END => break,
+                PCAPNG_OPTION_IF_NAME => {
+                    iface.name = Some(
+                        String::from_utf8_lossy(value)
+                            .trim_end_matches('\0')
+                            .to_string(),
+                    )
+                }
+                PCAPNG_OPTION_IF_TSRESOL if size == 1 => {
+                    let exponent = (value[0] & 0x7f) as u32;
+                    // the most significant bit tells if it's a power of 2 or 10
+                    let base: u64 = if value[0] & 0x80 != 0 { 2 } else { 10 };
+                    iface.resolution = base.checked_pow(exponent).unwrap_or(1_000_000);
+                }
+                _ => {}
+            }
+
+            // values are padded to 32 bits
+            let padded = (size + 3) & !3;
+            options = options.get(4 + padded..).unwrap_or_default();
+        }
+
+        if let Format::PcapNg { interfaces, .. } = &mut self.format {
+            interfaces.push(iface);
+        }
+
+        Ok(())
+    }
+
+    fn frame(
+        &mut self,
+        id: usize,
+        timestamp: u64,
+        data: &[u8],
+        size: usize,
+    ) -> Result<Frame, String> {
+        let iface = match &self.format {
+            Format::PcapNg { interfaces, .. } => interfaces
+                .get(id)
+                .ok_or(format!("unknown interface {}", id))?,
+            Format::Pcap { .. } => unreachable!(),
+        };
+
+        let timestamp = timestamp / iface.resolution.max(1);
+        let frame = Frame {
+            timestamp,
+            linktype: iface.linktype,
+            interface: iface.name.clone(),
+            data: data[..size.min(data.len())].to_vec(),
+        };
+        self.last_timestamp = timestamp;
+
+        Ok(frame)
+    }
+}
+
+impl Iterator for Reader {
+    type Item = Result<Captured, String>;
+
+    // returns the next mDNS packet, skipping everything else
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let frame = match self.next_frame() {
+                Ok(Some(frame)) => frame,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+
+            if let Some((source, destination, data)) = decode(frame.linktype, &frame.data) {
+                if source.port() == MULTICAST_PORT || destination.port() == MULTICAST_PORT {
+                    return Some(Ok(Captured {
+                        timestamp: frame.timestamp,
+                        interface: frame.interface,
+                        source,
+                        destination,
+                        data: data.to_vec(),
+                    }));
+                }
+            }
+        }
+    }
+}
+
+// returns the source, destination and payload of an UDP packet
+fn decode(linktype: u32, data: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let ip = match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]);
+            // skip 802.1Q tags
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                offset += 4;
+                ethertype = u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]);
+            }
+            match ethertype {
+                ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => data.get(offset + 2..)?,
+                _ => return None,
+            }
+        }
+        LINKTYPE_LINUX_SLL => data.get(16..)?,
+        LINKTYPE_LINUX_SLL2 => data.get(20..)?,
+        // the address family is in host byte order, the IP version tells it anyway
+        LINKTYPE_NULL | LINKTYPE_LOOP => data.get(4..)?,
+        LINKTYPE_RAW | LINKTYPE_RAW_OLD | LINKTYPE_IPV4 | LINKTYPE_IPV6 => data,
+        _ => return None,
+    };
+
+    let (source, destination, udp) = match ip.first()? >> 4 {
+        4 => decode_ipv4(ip)?,
+        6 => decode_ipv6(ip)?,
+        _ => return None,
+    };
+
+    if udp.len() < 8 {
+        return None;
+    }
+    let source_port = u16::from_be_bytes([udp[0], udp[1]]);
+    let destination_port = u16::from_be_bytes([udp[2], udp[3]]);
+    let size = u16::from_be_bytes([udp[4], udp[5]]) as usize;
+    let payload = udp.get(8..size.max(8).min(udp.len()))?;
+
+    Some((
+        SocketAddr::new(source, source_port),
+        SocketAddr::new(destination, destination_port),
+        payload,
+    ))
+}
+
+fn decode_ipv4(ip: &[u8]) -> Option<(IpAddr, IpAddr, &[u8])> {
+    if ip.len() < 20 || ip[9] != IPPROTO_UDP {
+        return None;
+    }
+    // fragments can't be parsed on their own
+    let fragment = u16::from_be_bytes([ip[6], ip[7]]);
+    if fragment & 0x3fff != 0 {
+        return None;
+    }
+
+    let header_size = (ip[0] & 0x0f) as usize * 4;
+    let total_size = u16::from_be_bytes([ip[2], ip[3]]) as usize;
+    let source = Ipv4Addr::new(ip[12], ip[13], ip[14], ip[15]);
+    let destination = Ipv4Addr::new(ip[16], ip[17], ip[18], ip[19]);
+
+    Some((
+        IpAddr::V4(source),
+        IpAddr::V4(destination),
+        ip.get(header_size..total_size.min(ip.len()))?,
+    ))
+}
+
+fn decode_ipv6(ip: &[u8]) -> Option<(IpAddr, IpAddr, &[u8])> {
+    if ip.len() < 40 {
+        return None;
+    }
+
+    let mut address = [0u8; 16];
+    address.copy_from_slice(&ip[8..24]);
+    let source = Ipv6Addr::from(address);
+    address.copy_from_slice(&ip[24..40]);
+    let destination = Ipv6Addr::from(address);
+
+    let payload_size = u16::from_be_bytes([ip[4], ip[5]]) as usize;
+    let mut payload = ip.get(40..(40 + payload_size).min(ip.len()))?;
+    let mut next_header = ip[6];
+
+    // skip the extension headers
+    loop {
+        match next_header {
+            IPPROTO_UDP => break,
+            // hop-by-hop, routing and destination options
+            0 | 43 | 60 => {
+                let size = (*payload.get(1)? as usize + 1) * 8;
+                next_header = *payload.first()?;
+                payload = payload.get(size..)?;
+            }
+            // fragments and everything else
+            _ => return None,
+        }
+    }
+
+    Some((IpAddr::V6(source), IpAddr::V6(destination), payload))
+}
+
+/// Whether a packet has been received or sent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// Writes mDNS packets to a pcapng file, as raw IP packets with one interface
+/// description for each discovery channel.
+pub struct Writer {
+    file: BufWriter<File>,
+    interfaces: u32,
+}
+
+// blocks and options are written in little endian, as declared by the section header
+fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+    let padded = (body.len() + 3) & !3;
+    let size = (12 + padded) as u32;
+
+    let mut block = Vec::with_capacity(size as usize);
+    block.extend_from_slice(&block_type.to_le_bytes());
+    block.extend_from_slice(&size.to_le_bytes());
+    block.extend_from_slice(body);
+    block.resize(8 + padded, 0);
+    block.extend_from_slice(&size.to_le_bytes());
+    block
+}
+
+fn option(options: &mut Vec<u8>, code: u16, value: &[u8]) {
+    options.extend_from_slice(&code.to_le_bytes());
+    options.extend_from_slice(&(value.len() as u16).to_le_bytes());
+    options.extend_from_slice(value);
+    options.resize((options.len() + 3) & !3, 0);
+}
+
+fn checksum(chunks: &[&[u8]]) -> u16 {
+    let mut sum = 0u32;
+    for chunk in chunks {
+        for word in chunk.chunks(2) {
+            let word = match word {
+                [high, low] => u16::from_be_bytes([*high, *low]),
+                [high] => u16::from_be_bytes([*high, 0]),
+                _ => 0,
+            };
+            sum += word as u32;
+        }
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+// builds the IP and UDP headers around the payload
+fn encode(source: &SocketAddr, destination: &SocketAddr, data: &[u8]) -> Option<Vec<u8>> {
+    let size = 8 + data.len();
+    let mut udp = Vec::with_capacity(size);
+    udp.extend_from_slice(&source.port().to_be_bytes());
+    udp.extend_from_slice(&destination.port().to_be_bytes());
+    udp.extend_from_slice(&(size as u16).to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(data);
+
+    let length = (size as u16).to_be_bytes();
+    let mut packet = match (source.ip(), destination.ip()) {
+        (IpAddr::V4(source), IpAddr::V4(destination)) => {
+            let pseudo = [
+                &source.octets()[..],
+                &destination.octets(),
+                &[0, IPPROTO_UDP],
+                &length,
+            ];
+            let sum = checksum(&[&pseudo.concat(), &udp]);
+            udp[6..8].copy_from_slice(&sum.to_be_bytes());
+
+            let mut header = vec![0x45, 0];
+            header.extend_from_slice(&((20 + size) as u16).to_be_bytes());
+            // id, flags and fragment offset, ttl, protocol and checksum
+            header.extend_from_slice(&[0, 0, 0, 0, 255, IPPROTO_UDP, 0, 0]);
+            header.extend_from_slice(&source.octets());
+            header.extend_from_slice(&destination.octets());
+            let sum = checksum(&[&header]);
+            header[10..12].copy_from_slice(&sum.to_be_bytes());
+            header
+        }
+        (IpAddr::V6(source), IpAddr::V6(destination)) => {
+            let pseudo = [
+                &source.octets()[..],
+                &destination.octets(),
+                &[0, 0],
+                &length,
+                &[0, 0, 0, IPPROTO_UDP],
+            ];
+            let sum = checksum(&[&pseudo.concat(), &udp]);
+            udp[6..8].copy_from_slice(&sum.to_be_bytes());
+
+            let mut header = vec![0x60, 0, 0, 0];
+            header.extend_from_slice(&length);
+            // next header and hop limit
+            header.extend_from_slice(&[IPPROTO_UDP, 255]);
+            header.extend_from_slice(&source.octets());
+            header.extend_from_slice(&destination.octets());
+            header
+        }
+        _ => return None,
+    };
+
+    packet.extend_from_slice(&udp);
+    Some(packet)
+}
+
+impl Writer {
+    pub fn create(path: &str) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut writer = Self {
+            file: BufWriter::new(file),
+            interfaces: 0,
+        };
+
+        // byte order magic, version 1.0 and unknown section length
+        let mut body = vec![];
+        body.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&(-1i64).to_le_bytes());
+        let application = format!("spycast {}", env!("CARGO_PKG_VERSION"));
+        option(
+            &mut body,
+            PCAPNG_OPTION_SHB_USERAPPL,
+            application.as_bytes(),
+        );
+        option(&mut body, PCAPNG_OPTION_END, &[]);
+
+        writer.write_block(PCAPNG_SECTION_HEADER, &body)?;
+        Ok(writer)
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> Result<(), String> {
+        self.file
+            .write_all(&block(block_type, body))
+            .and_then(|_| self.file.flush())
+            .map_err(|e| e.to_string())
+    }
+
+    /// Describes a new interface and returns its id, interfaces must be added before
+    /// writing their packets.
+    pub fn add_interface(
+        &mut self,
+        name: &str,
+        description: &str,
+        interface: Option<&Interface>,
+    ) -> Result<u32, String> {
+        // link type, reserved and snap length
+        let mut body = vec![];
+        body.extend_from_slice(&(LINKTYPE_RAW as u16).to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        option(&mut body, PCAPNG_OPTION_IF_NAME, name.as_bytes());
+        option(
+            &mut body,
+            PCAPNG_OPTION_IF_DESCRIPTION,
+            description.as_bytes(),
+        );
+        if let Some(iface) = interface {
+            for (ip, mask) in &iface.ipv4 {
+                option(
+                    &mut body,
+                    PCAPNG_OPTION_IF_IPV4ADDR,
+                    &[ip.octets(), mask.octets()].concat(),
+                );
+            }
+            for (ip, mask) in &iface.ipv6 {
+                let prefix = u128::from(*mask).count_ones() as u8;
+                option(
+                    &mut body,
+                    PCAPNG_OPTION_IF_IPV6ADDR,
+                    &[&ip.octets()[..], &[prefix]].concat(),
+                );
+            }
+        }
+        // microseconds
+        option(&mut body, PCAPNG_OPTION_IF_TSRESOL, &[6]);
+        option(&mut body, PCAPNG_OPTION_END, &[]);
+
+        self.write_block(PCAPNG_INTERFACE_DESCRIPTION, &body)?;
+
+        self.interfaces += 1;
+        Ok(self.interfaces - 1)
+    }
+
+    /// Writes an UDP packet with the current time.
+    pub fn write(
+        &mut self,
+        interface: u32,
+        direction: Direction,
+        source: &SocketAddr,
+        destination: &SocketAddr,
+        data: &[u8],
+    ) -> Result<(), String> {
+        if interface >= self.interfaces {
+            return Err(format!("unknown interface {}", interface));
+        }
+
+        let packet = encode(source, destination, data)
+            .ok_or(format!("can't mix {} and {}", source, destination))?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_micros() as u64)
+            .unwrap_or(0);
+
+        // interface id, timestamp, captured and original lengths
+        let mut body = vec![];
+        body.extend_from_slice(&interface.to_le_bytes());
+        body.extend_from_slice(&((timestamp >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(timestamp as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        body.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+        body.extend_from_slice(&packet);
+        body.resize((body.len() + 3) & !3, 0);
+
+        let flags: u32 = match direction {
+            Direction::Inbound => 1,
+            Direction::Outbound => 2,
+        };
+        option(&mut body, PCAPNG_OPTION_EPB_FLAGS, &flags.to_le_bytes());
+        option(&mut body, PCAPNG_OPTION_END, &[]);
+
+        self.write_block(PCAPNG_ENHANCED_PACKET, &body)
+    }
+}
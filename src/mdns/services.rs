@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+
+// names of well known ports from netbase's /etc/services, only used when nothing better is known
+const PORT_SERVICES: &str = include_str!("etc-services.csv");
+// descriptions of the services that are usually found over mDNS, these win over the port ones
+const KNOWN_SERVICES: &str = include_str!("services.csv");
+
+lazy_static! {
+    // _service._proto label -> description
+    static ref BUILTIN: HashMap<String, String> = {
+        let mut services = parse_ports(PORT_SERVICES).expect("could not parse the port services");
+        services.extend(parse(KNOWN_SERVICES).expect("could not parse the known services"));
+        services
+    };
+}
+
+fn reader(data: &str) -> csv::Reader<&[u8]> {
+    csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .flexible(true)
+        .from_reader(data.as_bytes())
+}
+
+// descriptions can span multiple lines
+fn clean(description: &str) -> String {
+    description
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+// columns are name, port, protocol and description, as in the first columns of the IANA
+// registry: https://www.iana.org/assignments/service-names-port-numbers/service-names-port-numbers.csv
+fn parse_ports(data: &str) -> Result<HashMap<String, String>, String> {
+    let mut services = HashMap::new();
+
+    for record in reader(data).records() {
+        let record = record.map_err(|e| e.to_string())?;
+        let name = record.get(0).unwrap_or_default().trim();
+        let proto = record.get(2).unwrap_or_default().trim();
+        let description = clean(record.get(3).unwrap_or_default());
+        // unassigned and reserved ports have no name
+        if name.is_empty() || proto.is_empty() || description.is_empty() {
+            continue;
+        }
+
+        services
+            .entry(format!("_{}._{}", name, proto).to_ascii_lowercase())
+            .or_insert(description);
+    }
+
+    Ok(services)
+}
+
+// columns are service, as in _airplay._tcp, and description
+fn parse(data: &str) -> Result<HashMap<String, String>, String> {
+    let mut services = HashMap::new();
+
+    for record in reader(data).records() {
+        let record = record.map_err(|e| e.to_string())?;
+        let name = record.get(0).unwrap_or_default().trim();
+        let description = clean(record.get(1).unwrap_or_default());
+        if name.is_empty() || description.is_empty() {
+            continue;
+        }
+
+        services.insert(name.trim_end_matches('.').to_ascii_lowercase(), description);
+    }
+
+    Ok(services)
+}
+
+// the IANA registry starts with a Service Name column
+fn is_registry(data: &str) -> bool {
+    reader(data)
+        .headers()
+        .map(|headers| headers.get(0).map(str::trim) == Some("Service Name"))
+        .unwrap_or(false)
+}
+
+/// The service descriptions used by an agent, the builtin ones unless more are loaded.
+#[derive(Clone, Default)]
+pub struct Services {
+    // win over the builtin ones
+    loaded: HashMap<String, String>,
+}
+
+impl Services {
+    /// Load service descriptions from a CSV file with service and description columns,
+    /// such as `_airplay._tcp,Apple TV`, or from the IANA service names registry. These
+    /// win over the ones already loaded.
+    pub fn load(&mut self, path: &str) -> Result<usize, String> {
+        let data = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let loaded = if is_registry(&data) {
+            parse_ports(&data)
+        } else {
+            parse(&data)
+        }
+        .map_err(|e| format!("{}: {}", path, e))?;
+        let count = loaded.len();
+
+        self.loaded.extend(loaded);
+
+        Ok(count)
+    }
+
+    /// Returns the description of the service type of this name, if known.
+    pub fn description(&self, name: &str) -> Option<String> {
+        labels(name)
+            .iter()
+            .find_map(|label| self.loaded.get(label).or_else(|| BUILTIN.get(label)))
+            .cloned()
+    }
+}
+
+// returns the labels that can describe this name, most specific first:
+// Printer._print._sub._ipp._tcp.local -> _print._sub._ipp._tcp, _sub._ipp._tcp, _ipp._tcp
+fn labels(name: &str) -> Vec<String> {
+    let name = name.trim_end_matches('.').to_ascii_lowercase();
+    let parts: Vec<&str> = name.split('.').collect();
+
+    let proto = match parts
+        .iter()
+        .rposition(|part| *part == "_tcp" || *part == "_udp")
+    {
+        Some(proto) if proto > 0 => proto,
+        _ => return vec![],
+    };
+
+    let mut labels = vec![];
+    for start in (proto.saturating_sub(3)..proto).rev() {
+        // only service and subtype labels
+        if !parts[start].starts_with('_') {
+            break;
+        }
+        labels.insert(0, parts[start..=proto].join("."));
+    }
+    labels
+}
@@ -0,0 +1,384 @@
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use crate::mdns::pcap;
+
+const RECV_BUFFER_SIZE: usize = 4096;
+
+/// A packet received by a [`Transport`].
+#[derive(Clone, Debug)]
+pub struct Packet {
+    pub source: SocketAddr,
+    // the address it's been sent to, if known
+    pub destination: Option<SocketAddr>,
+    pub data: Vec<u8>,
+    // unix timestamp in seconds, none if it's been received right now
+    pub timestamp: Option<u64>,
+    // the interface it's been received on, if known
+    pub interface: Option<String>,
+}
+
+impl Packet {
+    pub fn new(source: SocketAddr, data: Vec<u8>) -> Self {
+        Self {
+            source,
+            destination: None,
+            data,
+            timestamp: None,
+            interface: None,
+        }
+    }
+}
+
+/// Sends and receives the mDNS packets of a discovery channel.
+pub trait Transport: Send {
+    /// Send the data to this destination.
+    fn send_to(&self, data: &[u8], destination: &SocketAddr) -> io::Result<()>;
+
+    /// Wait up to timeout for the next packet. Returns none if nothing arrived and an error
+    /// of kind `UnexpectedEof` once no more packets will arrive.
+    fn recv(&mut self, timeout: Duration) -> io::Result<Option<Packet>>;
+
+    /// Another handle to the same transport, used to receive on a separate thread.
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>>;
+
+    /// False if the packets are not coming from the network this host is on, in which case
+    /// their sources are not looked up.
+    fn is_live(&self) -> bool {
+        true
+    }
+}
+
+/// Multicast UDP socket.
+pub struct UdpTransport {
+    socket: UdpSocket,
+    buffer: Vec<u8>,
+    // set if the destination address of the packets can be received
+    local_port: Option<u16>,
+}
+
+impl UdpTransport {
+    pub fn new(socket: UdpSocket) -> Self {
+        let local_port = match (pktinfo::enable(&socket), socket.local_addr()) {
+            (Ok(()), Ok(address)) => Some(address.port()),
+            _ => None,
+        };
+
+        Self {
+            socket,
+            buffer: vec![0; RECV_BUFFER_SIZE],
+            local_port,
+        }
+    }
+
+    // returns the size, source and destination of the next packet
+    fn recv_from(&mut self) -> io::Result<(usize, SocketAddr, Option<SocketAddr>)> {
+        match self.local_port {
+            Some(port) => {
+                let (count, source, destination) = pktinfo::recv(&self.socket, &mut self.buffer)?;
+                Ok((
+                    count,
+                    source,
+                    destination.map(|ip| SocketAddr::new(ip, port)),
+                ))
+            }
+            None => {
+                let (count, source) = self.socket.recv_from(&mut self.buffer)?;
+                Ok((count, source, None))
+            }
+        }
+    }
+}
+
+// the destination of the packets is only known from the ancillary data of recvmsg
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod pktinfo {
+    use std::io;
+    use std::mem;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+
+    pub fn enable(socket: &UdpSocket) -> io::Result<()> {
+        let (level, name) = match socket.local_addr()? {
+            SocketAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_PKTINFO),
+            SocketAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO),
+        };
+        let enabled: libc::c_int = 1;
+
+        let ret = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                &enabled as *const libc::c_int as *const libc::c_void,
+                mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    pub fn recv(
+        socket: &UdpSocket,
+        buffer: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+        let mut source: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let mut iov = libc::iovec {
+            iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buffer.len(),
+        };
+        // large and aligned enough for either pktinfo
+        let mut control = [0u64; 16];
+
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_name = &mut source as *mut libc::sockaddr_storage as *mut libc::c_void;
+        msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = mem::size_of_val(&control) as _;
+
+        let count = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+        if count < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let source = unsafe { to_socket_addr(&source) }
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown source family"))?;
+
+        let mut destination = None;
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                let data = libc::CMSG_DATA(cmsg);
+                match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                    (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                        let info = ptr::read_unaligned(data as *const libc::in_pktinfo);
+                        destination = Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                            info.ipi_addr.s_addr,
+                        ))));
+                    }
+                    (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                        let info = ptr::read_unaligned(data as *const libc::in6_pktinfo);
+                        destination = Some(IpAddr::V6(Ipv6Addr::from(info.ipi6_addr.s6_addr)));
+                    }
+                    _ => {}
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+
+        Ok((count as usize, source, destination))
+    }
+
+    unsafe fn to_socket_addr(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+        match storage.ss_family as libc::c_int {
+            libc::AF_INET => {
+                let address = &*(storage as *const _ as *const libc::sockaddr_in);
+                Some(SocketAddr::new(
+                    IpAddr::V4(Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr))),
+                    u16::from_be(address.sin_port),
+                ))
+            }
+            libc::AF_INET6 => {
+                let address = &*(storage as *const _ as *const libc::sockaddr_in6);
+                Some(SocketAddr::V6(std::net::SocketAddrV6::new(
+                    Ipv6Addr::from(address.sin6_addr.s6_addr),
+                    u16::from_be(address.sin6_port),
+                    address.sin6_flowinfo,
+                    address.sin6_scope_id,
+                )))
+            }
+            _ => None,
+        }
+    }
+}
+
+// elsewhere the destination is not known
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+mod pktinfo {
+    use std::io;
+    use std::net::{IpAddr, SocketAddr, UdpSocket};
+
+    pub fn enable(_socket: &UdpSocket) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub fn recv(
+        _socket: &UdpSocket,
+        _buffer: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send_to(&self, data: &[u8], destination: &SocketAddr) -> io::Result<()> {
+        self.socket.send_to(data, destination).map(|_| ())
+    }
+
+    fn recv(&mut self, timeout: Duration) -> io::Result<Option<Packet>> {
+        self.socket.set_read_timeout(Some(timeout))?;
+        match self.recv_from() {
+            Ok((0, _, _)) => Ok(None),
+            Ok((count, source, destination)) => Ok(Some(Packet {
+                destination,
+                ..Packet::new(source, self.buffer[..count].to_vec())
+            })),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(Self::new(self.socket.try_clone()?)))
+    }
+}
+
+/// Replays the mDNS packets of a pcap or pcapng file with their capture timestamps.
+#[derive(Clone)]
+pub struct PcapTransport {
+    // none once the capture has been fully read
+    reader: Arc<Mutex<Option<pcap::Reader>>>,
+}
+
+impl PcapTransport {
+    pub fn open(path: &str) -> Result<Self, String> {
+        Ok(Self {
+            reader: Arc::new(Mutex::new(Some(pcap::Reader::open(path)?))),
+        })
+    }
+}
+
+impl Transport for PcapTransport {
+    fn send_to(&self, _data: &[u8], _destination: &SocketAddr) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "captures are read only",
+        ))
+    }
+
+    fn recv(&mut self, _timeout: Duration) -> io::Result<Option<Packet>> {
+        let mut guard = self.reader.lock().unwrap();
+
+        let next = match guard.as_mut() {
+            Some(reader) => reader.next(),
+            None => None,
+        };
+
+        match next {
+            Some(Ok(captured)) => Ok(Some(Packet {
+                source: captured.source,
+                destination: Some(captured.destination),
+                data: captured.data,
+                timestamp: Some(captured.timestamp),
+                interface: captured.interface,
+            })),
+            Some(Err(e)) => {
+                // the rest of the file can't be trusted
+                *guard = None;
+                Err(io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+            None => {
+                *guard = None;
+                Err(io::ErrorKind::UnexpectedEof.into())
+            }
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn is_live(&self) -> bool {
+        false
+    }
+}
+
+/// Exchanges packets in memory with a [`MemoryPeer`], useful for tests.
+#[derive(Clone)]
+pub struct MemoryTransport {
+    inbound: Arc<Mutex<mpsc::Receiver<Packet>>>,
+    outbound: mpsc::Sender<(SocketAddr, Vec<u8>)>,
+}
+
+/// The other end of a [`MemoryTransport`], the transport is closed when this is dropped.
+pub struct MemoryPeer {
+    inbound: mpsc::Sender<Packet>,
+    outbound: mpsc::Receiver<(SocketAddr, Vec<u8>)>,
+}
+
+impl MemoryTransport {
+    pub fn pair() -> (MemoryTransport, MemoryPeer) {
+        let (inbound_tx, inbound_rx) = mpsc::channel();
+        let (outbound_tx, outbound_rx) = mpsc::channel();
+
+        (
+            MemoryTransport {
+                inbound: Arc::new(Mutex::new(inbound_rx)),
+                outbound: outbound_tx,
+            },
+            MemoryPeer {
+                inbound: inbound_tx,
+                outbound: outbound_rx,
+            },
+        )
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn send_to(&self, data: &[u8], destination: &SocketAddr) -> io::Result<()> {
+        self.outbound
+            .send((*destination, data.to_vec()))
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn recv(&mut self, timeout: Duration) -> io::Result<Option<Packet>> {
+        let inbound = self.inbound.lock().unwrap();
+
+        match inbound.recv_timeout(timeout) {
+            Ok(packet) => Ok(Some(packet)),
+            Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+
+    fn try_clone(&self) -> io::Result<Box<dyn Transport>> {
+        Ok(Box::new(self.clone()))
+    }
+
+    fn is_live(&self) -> bool {
+        false
+    }
+}
+
+impl MemoryPeer {
+    /// Deliver a packet to the transport, returns false if the transport is gone.
+    pub fn send(&self, packet: Packet) -> bool {
+        self.inbound.send(packet).is_ok()
+    }
+
+    /// Wait up to timeout for the next packet sent through the transport.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<(SocketAddr, Vec<u8>)> {
+        self.outbound.recv_timeout(timeout).ok()
+    }
+
+    /// The packets sent through the transport that have not been received yet.
+    pub fn sent(&self) -> Vec<(SocketAddr, Vec<u8>)> {
+        self.outbound.try_iter().collect()
+    }
+}
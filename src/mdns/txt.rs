@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+// https://datatracker.ietf.org/doc/html/rfc6763#section-6
+pub type TxtRecord = HashMap<String, TxtValue>;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum TxtValue {
+    // key without '=', an attribute that is either present or not
+    Flag(bool),
+    Text(String),
+    // values that are not valid UTF-8, hex encoded
+    Binary { hex: String },
+}
+
+impl fmt::Display for TxtValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxtValue::Flag(flag) => write!(f, "{}", flag),
+            TxtValue::Text(text) => write!(f, "{}", text),
+            TxtValue::Binary { hex } => write!(f, "0x{}", hex),
+        }
+    }
+}
+
+pub fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn parse<'a>(chunks: impl Iterator<Item = &'a [u8]>) -> TxtRecord {
+    let mut record = TxtRecord::new();
+
+    for chunk in chunks {
+        let (key, value) = match chunk.iter().position(|&b| b == b'=') {
+            Some(pos) => (&chunk[..pos], Some(&chunk[pos + 1..])),
+            None => (chunk, None),
+        };
+
+        // keys are printable US-ASCII, empty keys must be ignored
+        if key.is_empty() || !key.iter().all(|b| (0x20..=0x7e).contains(b)) {
+            continue;
+        }
+
+        // keys are case insensitive and only the first occurrence counts
+        let key = String::from_utf8_lossy(key).to_ascii_lowercase();
+        if record.contains_key(&key) {
+            continue;
+        }
+
+        let value = match value {
+            None => TxtValue::Flag(true),
+            Some(value) => match std::str::from_utf8(value) {
+                Ok(text) => TxtValue::Text(text.to_owned()),
+                Err(_) => TxtValue::Binary { hex: to_hex(value) },
+            },
+        };
+
+        record.insert(key, value);
+    }
+
+    record
+}
@@ -0,0 +1,159 @@
+//! Snapshots of the endpoints as one JSON file each inside a folder.
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use crate::mdns::discovery::{MappedEndpoints, SharedEndpoints};
+use crate::mdns::event::Timestamped;
+use crate::mdns::Endpoint;
+
+/// The name of the file the endpoint with this address is saved to, colons are replaced
+/// since they're not allowed on every platform.
+pub fn filename(address: &IpAddr) -> String {
+    format!("{}.json", address.to_string().replace(':', "_"))
+}
+
+// writes a temporary file first so that a snapshot is never left half written
+fn write(path: &Path, endpoint: &Endpoint) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(endpoint).map_err(|e| e.to_string())?;
+    let filepath = path.join(filename(&endpoint.address));
+    let temp = filepath.with_extension("json.tmp");
+
+    let written = fs::File::create(&temp)
+        .and_then(|mut file| {
+            file.write_all(json.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp, &filepath));
+
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp);
+        return Err(e.to_string());
+    }
+
+    Ok(())
+}
+
+/// Load the endpoints saved inside this folder, the files that can't be read are skipped.
+pub fn load(path: &str) -> MappedEndpoints {
+    let mut endpoints = MappedEndpoints::new();
+
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        // nothing saved yet
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return endpoints,
+        Err(e) => {
+            eprintln!("could not read {}: {}", path, e);
+            return endpoints;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let filepath = entry.path();
+        if filepath.extension().and_then(|ext| ext.to_str()) != Some("json") {
+            continue;
+        }
+
+        let endpoint = fs::read_to_string(&filepath)
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_str::<Endpoint>(&json).map_err(|e| e.to_string()));
+        match endpoint {
+            Ok(endpoint) => {
+                endpoints.insert(endpoint.address, endpoint);
+            }
+            Err(e) => eprintln!("could not restore {}: {}", filepath.display(), e),
+        }
+    }
+
+    endpoints
+}
+
+/// Saves the endpoints that changed inside a folder, at most once per interval.
+pub struct Writer {
+    path: PathBuf,
+    interval: Duration,
+    endpoints: SharedEndpoints,
+    // changed since the last write
+    dirty: HashSet<IpAddr>,
+}
+
+impl Writer {
+    pub fn new(path: &str, interval: Duration, endpoints: SharedEndpoints) -> Self {
+        Self {
+            path: PathBuf::from(path),
+            interval,
+            endpoints,
+            dirty: HashSet::new(),
+        }
+    }
+
+    /// Save the endpoints changed by these events until the stream is closed, then save
+    /// what's left. Meant to run on its own thread.
+    pub fn run(mut self, events: mpsc::Receiver<Timestamped>) {
+        // set once something changed
+        let mut next_write: Option<Instant> = None;
+
+        loop {
+            let received = match next_write {
+                Some(at) => events.recv_timeout(at.saturating_duration_since(Instant::now())),
+                None => events
+                    .recv()
+                    .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+            };
+
+            match received {
+                Ok(Timestamped { event, .. }) => {
+                    self.dirty.insert(event.endpoint_address());
+                    next_write.get_or_insert_with(|| Instant::now() + self.interval);
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {
+                    self.flush();
+                    // retry the failed ones later
+                    next_write = if self.dirty.is_empty() {
+                        None
+                    } else {
+                        Some(Instant::now() + self.interval)
+                    };
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    self.flush();
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Save the endpoints that changed now, the ones that can't be saved are kept for the
+    /// next time.
+    pub fn flush(&mut self) {
+        if self.dirty.is_empty() {
+            return;
+        }
+
+        if let Err(e) = fs::create_dir_all(&self.path) {
+            eprintln!("could not save to {}: {}", self.path.display(), e);
+            return;
+        }
+
+        // copied so that the agent is not blocked while writing
+        let changed: Vec<Endpoint> = match self.endpoints.lock() {
+            Ok(guard) => self
+                .dirty
+                .drain()
+                .filter_map(|address| guard.get(&address).cloned())
+                .collect(),
+            Err(_) => return,
+        };
+
+        for endpoint in changed {
+            if let Err(e) = write(&self.path, &endpoint) {
+                eprintln!("could not save {}: {}", endpoint.address, e);
+                self.dirty.insert(endpoint.address);
+            }
+        }
+    }
+}
@@ -1,17 +1,44 @@
-use crate::mdns::discovery::{MappedEndpoints, SharedEndpoints};
+use std::sync::mpsc::Receiver;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use spycast::{MappedEndpoints, SharedEndpoints};
 
 #[tauri::command]
 fn get_state(state: tauri::State<'_, SharedEndpoints>) -> MappedEndpoints {
     state.lock().unwrap().clone()
 }
 
-pub fn run<T>(state: T)
+// shows the UI until it's closed or until the agent has finished, then calls on_exit
+// before the process exits
+pub fn run<T, F>(state: T, finished: Receiver<()>, on_exit: F)
 where
     T: Send + Sync + 'static,
+    F: FnOnce() + Send + 'static,
 {
-    tauri::Builder::default()
+    let app = tauri::Builder::default()
         .manage(state)
         .invoke_handler(tauri::generate_handler![get_state])
-        .run(tauri::generate_context!())
+        .build(tauri::generate_context!())
         .expect("error while running tauri application");
+
+    // called only once, by whichever of the two happens first
+    let on_exit = Arc::new(Mutex::new(Some(on_exit)));
+    let exit_once = move || on_exit.lock().unwrap().take().map(|on_exit| on_exit());
+
+    // the agent reached its --duration or --max-idle, close the app ourselves since
+    // exiting from a handle doesn't go through RunEvent::Exit
+    let handle = app.handle();
+    let agent_exit = exit_once.clone();
+    thread::spawn(move || {
+        if finished.recv().is_ok() && agent_exit().is_some() {
+            handle.exit(0);
+        }
+    });
+
+    app.run(move |_, event| {
+        if let tauri::RunEvent::Exit = event {
+            exit_once();
+        }
+    });
 }
@@ -0,0 +1,230 @@
+// builds the mDNS traffic of the devices used by the tests and runs an agent over it
+#![allow(dead_code)]
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::thread;
+
+use spycast::mdns::transport::Packet;
+use spycast::{AgentBuilder, MappedEndpoints, MemoryTransport};
+
+pub const A: u16 = 1;
+pub const PTR: u16 = 12;
+pub const TXT: u16 = 16;
+pub const SRV: u16 = 33;
+
+pub const TTL: u32 = 120;
+// timestamp of the first packet
+pub const T0: u64 = 1_700_000_000;
+
+pub const CHROMECAST: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 20);
+pub const APPLE_TV: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 21);
+pub const PRINTER: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 22);
+pub const HOMEKIT: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 23);
+// only sends queries
+pub const LAPTOP: Ipv4Addr = Ipv4Addr::new(192, 168, 1, 30);
+
+// a file or folder in the temp folder, removed if it's left from a previous run
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("spycast-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_dir_all(&path);
+    path
+}
+
+pub fn name(name: &str) -> Vec<u8> {
+    let mut data = vec![];
+    for label in name.trim_end_matches('.').split('.') {
+        data.push(label.len() as u8);
+        data.extend(label.as_bytes());
+    }
+    data.push(0);
+    data
+}
+
+pub fn record(owner: &str, rtype: u16, ttl: u32, rdata: &[u8]) -> Vec<u8> {
+    let mut data = name(owner);
+    data.extend(rtype.to_be_bytes());
+    // IN class
+    data.extend(1u16.to_be_bytes());
+    data.extend(ttl.to_be_bytes());
+    data.extend((rdata.len() as u16).to_be_bytes());
+    data.extend(rdata);
+    data
+}
+
+pub fn ptr(owner: &str, target: &str, ttl: u32) -> Vec<u8> {
+    record(owner, PTR, ttl, &name(target))
+}
+
+pub fn srv(owner: &str, port: u16, target: &str, ttl: u32) -> Vec<u8> {
+    // priority and weight
+    let mut rdata = vec![0, 0, 0, 0];
+    rdata.extend(port.to_be_bytes());
+    rdata.extend(name(target));
+    record(owner, SRV, ttl, &rdata)
+}
+
+pub fn txt(owner: &str, entries: &[&str], ttl: u32) -> Vec<u8> {
+    let mut rdata = vec![];
+    for entry in entries {
+        rdata.push(entry.len() as u8);
+        rdata.extend(entry.as_bytes());
+    }
+    record(owner, TXT, ttl, &rdata)
+}
+
+pub fn a(owner: &str, address: Ipv4Addr, ttl: u32) -> Vec<u8> {
+    record(owner, A, ttl, &address.octets())
+}
+
+fn message(flags: u16, questions: &[u8], qdcount: usize, records: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = vec![0, 0];
+    data.extend(flags.to_be_bytes());
+    data.extend((qdcount as u16).to_be_bytes());
+    data.extend((records.len() as u16).to_be_bytes());
+    data.extend([0, 0, 0, 0]);
+    data.extend(questions);
+    for record in records {
+        data.extend(record);
+    }
+    data
+}
+
+pub fn response(records: &[Vec<u8>]) -> Vec<u8> {
+    // authoritative answer
+    message(0x8400, &[], 0, records)
+}
+
+// questions are (name, type, unicast response), known answers are sent as answers
+pub fn query(questions: &[(&str, u16, bool)], known_answers: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = vec![];
+    for (qname, qtype, unicast) in questions {
+        data.extend(name(qname));
+        data.extend(qtype.to_be_bytes());
+        let class: u16 = if *unicast { 0x8001 } else { 1 };
+        data.extend(class.to_be_bytes());
+    }
+    message(0, &data, questions.len(), known_answers)
+}
+
+// the records a device announces for one of its services
+pub fn announcement(
+    service_type: &str,
+    instance: &str,
+    host: &str,
+    address: Ipv4Addr,
+    port: u16,
+    entries: &[&str],
+    ttl: u32,
+) -> Vec<Vec<u8>> {
+    let service_type = format!("{}.local", service_type);
+    let instance = format!("{}.{}", instance, service_type);
+    let host = format!("{}.local", host);
+
+    vec![
+        ptr(&service_type, &instance, ttl),
+        srv(&instance, port, &host, ttl),
+        txt(&instance, entries, ttl),
+        a(&host, address, ttl),
+    ]
+}
+
+pub fn chromecast(address: Ipv4Addr, ttl: u32) -> Vec<u8> {
+    response(&announcement(
+        "_googlecast._tcp",
+        "Chromecast-Ultra-4f2a",
+        "4f2a",
+        address,
+        8009,
+        &[
+            "id=4f2a",
+            "md=Chromecast Ultra",
+            "fn=Living Room TV",
+            "ve=05",
+        ],
+        ttl,
+    ))
+}
+
+pub fn airplay(address: Ipv4Addr, ttl: u32) -> Vec<u8> {
+    response(&announcement(
+        "_airplay._tcp",
+        "Bedroom",
+        "Bedroom-Apple-TV",
+        address,
+        7000,
+        &["model=AppleTV11,1", "osvers=17.1", "srcvers=710.78.2"],
+        ttl,
+    ))
+}
+
+pub fn printer(address: Ipv4Addr, ttl: u32) -> Vec<u8> {
+    response(&announcement(
+        "_ipp._tcp",
+        "Office Printer",
+        "NPI3C2A1B",
+        address,
+        631,
+        &["txtvers=1", "ty=HP LaserJet Pro M404dn", "rp=ipp/print"],
+        ttl,
+    ))
+}
+
+pub fn homekit(address: Ipv4Addr, ttl: u32) -> Vec<u8> {
+    response(&announcement(
+        "_hap._tcp",
+        "Eve Energy 2B1C",
+        "Eve-Energy-2B1C",
+        address,
+        80,
+        &[
+            "c#=3",
+            "ci=7",
+            "id=2B:1C:3D:4E:5F:60",
+            "md=Eve Energy",
+            "sf=0",
+        ],
+        ttl,
+    ))
+}
+
+pub fn packet(source: Ipv4Addr, data: Vec<u8>, timestamp: u64) -> Packet {
+    Packet {
+        source: SocketAddr::new(IpAddr::V4(source), 5353),
+        destination: None,
+        data,
+        timestamp: Some(timestamp),
+        interface: Some("eth0".to_string()),
+    }
+}
+
+// the chromecast and the printer announcing their services
+pub fn two_devices() -> Vec<Packet> {
+    vec![
+        packet(CHROMECAST, chromecast(CHROMECAST, TTL), T0),
+        packet(PRINTER, printer(PRINTER, TTL), T0),
+    ]
+}
+
+// runs a passive agent over these packets until all of them have been handled
+pub fn discover(packets: Vec<Packet>) -> MappedEndpoints {
+    discover_with(AgentBuilder::new().passive(true), packets)
+}
+
+pub fn discover_with(builder: AgentBuilder, packets: Vec<Packet>) -> MappedEndpoints {
+    let (transport, peer) = MemoryTransport::pair();
+    let mut agent = builder.transport(transport).build().unwrap();
+    let endpoints = agent.endpoints();
+
+    let handle = thread::spawn(move || agent.start());
+    for packet in packets {
+        assert!(peer.send(packet));
+    }
+    // closes the transport, the agent stops once everything has been received
+    drop(peer);
+    handle.join().unwrap();
+
+    let endpoints = endpoints.lock().unwrap();
+    endpoints.clone()
+}
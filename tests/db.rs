@@ -0,0 +1,135 @@
+use std::net::IpAddr;
+
+use spycast::{Agent, Database};
+
+mod common;
+use common::*;
+
+fn temp_db(name: &str) -> String {
+    let path = temp_path(&format!("{}.db", name));
+    path.to_str().unwrap().to_owned()
+}
+
+fn count(path: &str, query: &str) -> i64 {
+    let conn = rusqlite::Connection::open(path).unwrap();
+    conn.query_row(query, [], |row| row.get(0)).unwrap()
+}
+
+// runs a scan over these packets storing everything in the database
+fn scan(path: &str, resume: bool, packets: Vec<spycast::mdns::transport::Packet>) {
+    let mut db = Database::open(path).unwrap();
+    let mut builder = Agent::builder().passive(true);
+    if resume {
+        builder = builder.restore(db.endpoints().unwrap());
+    }
+
+    let (transport, peer) = spycast::MemoryTransport::pair();
+    let mut agent = builder.transport(transport).build().unwrap();
+    db.start_scan().unwrap();
+    let endpoints = agent.endpoints();
+    let events = agent.subscribe();
+    let handle = std::thread::spawn(move || agent.start());
+
+    for packet in packets {
+        peer.send(packet);
+    }
+    drop(peer);
+
+    for event in events {
+        let address = event.event.endpoint_address();
+        let endpoint = endpoints.lock().unwrap()[&address].clone();
+        db.update(&event, &endpoint).unwrap();
+    }
+    handle.join().unwrap();
+    db.finish(&endpoints.lock().unwrap()).unwrap();
+}
+
+#[test]
+fn stores_endpoints() {
+    let path = temp_db("store");
+    scan(&path, false, two_devices());
+
+    assert_eq!(count(&path, "SELECT COUNT(*) FROM endpoints"), 2);
+    assert_eq!(
+        count(
+            &path,
+            "SELECT COUNT(*) FROM services WHERE address = '192.168.1.20'"
+        ),
+        3
+    );
+    assert_eq!(
+        count(&path, "SELECT COUNT(*) FROM records WHERE key = 'ipv4'"),
+        2
+    );
+    assert_eq!(
+        count(
+            &path,
+            "SELECT COUNT(*) FROM fingerprints WHERE kind = 'chromecast' AND model = 'Chromecast Ultra'"
+        ),
+        1
+    );
+    assert_eq!(
+        count(
+            &path,
+            "SELECT COUNT(*) FROM scans WHERE finished_at IS NOT NULL"
+        ),
+        1
+    );
+
+    // opening it doesn't start a scan
+    let restored = Database::open(&path).unwrap().endpoints().unwrap();
+    assert_eq!(count(&path, "SELECT COUNT(*) FROM scans"), 1);
+    let endpoint = &restored[&IpAddr::V4(CHROMECAST)];
+    assert_eq!(endpoint.fingerprint.as_ref().unwrap().kind, "chromecast");
+    assert_eq!(endpoint.instances.len(), 1);
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn scans_are_appended() {
+    let path = temp_db("append");
+    scan(
+        &path,
+        false,
+        vec![packet(CHROMECAST, chromecast(CHROMECAST, TTL), T0)],
+    );
+    // the chromecast is gone by the second scan
+    scan(
+        &path,
+        true,
+        vec![
+            packet(PRINTER, printer(PRINTER, TTL), T0 + 600),
+            packet(CHROMECAST, chromecast(CHROMECAST, 0), T0 + 601),
+        ],
+    );
+
+    assert_eq!(count(&path, "SELECT COUNT(*) FROM scans"), 2);
+    assert_eq!(count(&path, "SELECT COUNT(*) FROM endpoints"), 2);
+    assert_eq!(
+        count(
+            &path,
+            "SELECT COUNT(*) FROM sightings WHERE address = '192.168.1.20' AND event = 'discovered'"
+        ),
+        1
+    );
+    // at the time of the packets
+    assert_eq!(
+        count(&path, "SELECT seen_at FROM sightings WHERE event = 'gone'"),
+        (T0 + 601) as i64
+    );
+    // history is kept
+    assert_eq!(
+        count(&path, "SELECT COUNT(*) FROM records WHERE key = 'ipv4'"),
+        2
+    );
+    assert_eq!(
+        count(
+            &path,
+            "SELECT online FROM endpoints WHERE address = '192.168.1.20'"
+        ),
+        0
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
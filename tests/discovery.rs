@@ -0,0 +1,713 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::thread;
+use std::time::Duration;
+
+use spycast::mdns::pcap;
+use spycast::mdns::transport::Packet;
+use spycast::{Agent, Endpoint, Event, MappedEndpoints, MemoryTransport, Transport, UdpTransport};
+
+mod common;
+use common::*;
+
+fn endpoint(endpoints: &MappedEndpoints, address: Ipv4Addr) -> &Endpoint {
+    endpoints
+        .get(&IpAddr::V4(address))
+        .unwrap_or_else(|| panic!("{} has not been discovered", address))
+}
+
+#[test]
+fn fingerprints_chromecast() {
+    let endpoints = discover(vec![packet(CHROMECAST, chromecast(CHROMECAST, TTL), T0)]);
+    assert_eq!(endpoints.len(), 1);
+
+    let endpoint = endpoint(&endpoints, CHROMECAST);
+    assert!(endpoint.online);
+    assert_eq!(endpoint.name.as_deref(), Some("4f2a.local"));
+    assert_eq!(endpoint.interface.as_deref(), Some("eth0"));
+    assert!(!endpoint.local);
+    assert_eq!(endpoint.first_seen, T0);
+
+    let instance = &endpoint.instances["Chromecast-Ultra-4f2a._googlecast._tcp.local"];
+    assert_eq!(instance.service_type, "_googlecast._tcp.local");
+    assert_eq!(instance.host.as_deref(), Some("4f2a.local"));
+    assert_eq!(instance.port, Some(8009));
+    assert_eq!(instance.addresses, vec![IpAddr::V4(CHROMECAST)]);
+
+    // every value keeps the type of the record it comes from
+    let host = &endpoint.services["4f2a.local"].properties;
+    assert_eq!(host.get("ipv4").unwrap()[0].record_type, "A");
+    let srv = &endpoint.services["Chromecast-Ultra-4f2a._googlecast._tcp.local"].properties;
+    assert_eq!(srv.get("server").unwrap()[0].record_type, "SRV");
+
+    let fingerprint = endpoint.fingerprint.as_ref().unwrap();
+    assert_eq!(fingerprint.vendor, "google");
+    assert_eq!(fingerprint.kind, "chromecast");
+    assert_eq!(fingerprint.model.as_deref(), Some("Chromecast Ultra"));
+    assert_eq!(fingerprint.friendly_name.as_deref(), Some("Living Room TV"));
+}
+
+#[test]
+fn fingerprints_airplay() {
+    let endpoints = discover(vec![packet(APPLE_TV, airplay(APPLE_TV, TTL), T0)]);
+    let endpoint = endpoint(&endpoints, APPLE_TV);
+
+    let service = &endpoint.services["Bedroom._airplay._tcp.local"];
+    assert_eq!(service.srv.len(), 1);
+    assert_eq!(service.srv[0].port, 7000);
+    assert_eq!(service.srv[0].addresses, vec![IpAddr::V4(APPLE_TV)]);
+    assert!(endpoint.services["_airplay._tcp.local"]
+        .description
+        .is_some());
+
+    let fingerprint = endpoint.fingerprint.as_ref().unwrap();
+    assert_eq!(fingerprint.vendor, "apple");
+    assert_eq!(fingerprint.kind, "appletv");
+    assert_eq!(fingerprint.model.as_deref(), Some("AppleTV11,1"));
+    assert_eq!(fingerprint.os.as_deref(), Some("tvOS"));
+    assert_eq!(fingerprint.os_version.as_deref(), Some("17.1"));
+    assert_eq!(fingerprint.firmware.as_deref(), Some("710.78.2"));
+    assert_eq!(fingerprint.friendly_name.as_deref(), Some("Bedroom"));
+}
+
+#[test]
+fn fingerprints_printer() {
+    let endpoints = discover(vec![packet(PRINTER, printer(PRINTER, TTL), T0)]);
+    let endpoint = endpoint(&endpoints, PRINTER);
+
+    let instance = &endpoint.instances["Office Printer._ipp._tcp.local"];
+    assert_eq!(instance.port, Some(631));
+
+    let fingerprint = endpoint.fingerprint.as_ref().unwrap();
+    assert_eq!(fingerprint.kind, "printer");
+    assert_eq!(fingerprint.model.as_deref(), Some("HP LaserJet Pro M404dn"));
+    assert_eq!(fingerprint.friendly_name.as_deref(), Some("Office Printer"));
+
+    let candidate = &endpoint.candidates[0];
+    assert_eq!(candidate.kind, "printer");
+    assert!(!candidate.evidence.is_empty());
+}
+
+#[test]
+fn fingerprints_homekit() {
+    let endpoints = discover(vec![packet(HOMEKIT, homekit(HOMEKIT, TTL), T0)]);
+    let endpoint = endpoint(&endpoints, HOMEKIT);
+
+    let fingerprint = endpoint.fingerprint.as_ref().unwrap();
+    assert_eq!(fingerprint.kind, "homekit");
+    assert_eq!(fingerprint.model.as_deref(), Some("Eve Energy"));
+    assert_eq!(
+        fingerprint.friendly_name.as_deref(),
+        Some("Eve Energy 2B1C")
+    );
+}
+
+#[test]
+fn extra_rules_stay_with_their_agent() {
+    let path = temp_path("rules.json");
+    let path = path.to_str().unwrap().to_owned();
+    std::fs::write(
+        &path,
+        r#"[{ "vendor": "acme", "kind": "copier", "confidence": 0.99, "port": 631 }]"#,
+    )
+    .unwrap();
+
+    let packets = || vec![packet(PRINTER, printer(PRINTER, TTL), T0)];
+    let with_rules = || Agent::builder().passive(true).fingerprints(&path);
+    // built more than once, the rules are not loaded again
+    discover_with(with_rules(), packets());
+    let custom = discover_with(with_rules(), packets());
+    let builtin = discover(packets());
+    std::fs::remove_file(&path).unwrap();
+
+    let fingerprint = endpoint(&custom, PRINTER).fingerprint.clone().unwrap();
+    assert_eq!(fingerprint.kind, "copier");
+    assert_eq!(fingerprint.confidence, 0.99);
+    let fingerprint = endpoint(&builtin, PRINTER).fingerprint.clone().unwrap();
+    assert_eq!(fingerprint.kind, "printer");
+}
+
+#[test]
+fn extra_services_stay_with_their_agent() {
+    // in the layout of the IANA registry
+    let path = temp_path("services.csv");
+    let path = path.to_str().unwrap().to_owned();
+    std::fs::write(
+        &path,
+        "Service Name,Port Number,Transport Protocol,Description\n\
+         googlecast,8009,tcp,Custom Cast\n",
+    )
+    .unwrap();
+
+    let packets = || vec![packet(CHROMECAST, chromecast(CHROMECAST, TTL), T0)];
+    let custom = discover_with(Agent::builder().passive(true).services(&path), packets());
+    let builtin = discover(packets());
+    std::fs::remove_file(&path).unwrap();
+
+    let description = |endpoints: &MappedEndpoints| {
+        endpoint(endpoints, CHROMECAST).services["_googlecast._tcp.local"]
+            .description
+            .clone()
+    };
+    assert_eq!(description(&custom).as_deref(), Some("Custom Cast"));
+    assert_eq!(
+        description(&builtin).as_deref(),
+        Some("Google Cast (Chromecast)")
+    );
+}
+
+#[test]
+fn multiple_devices() {
+    let endpoints = discover(vec![
+        packet(CHROMECAST, chromecast(CHROMECAST, TTL), T0),
+        packet(APPLE_TV, airplay(APPLE_TV, TTL), T0),
+        packet(PRINTER, printer(PRINTER, TTL), T0 + 1),
+        packet(HOMEKIT, homekit(HOMEKIT, TTL), T0 + 2),
+    ]);
+    assert_eq!(endpoints.len(), 4);
+
+    for (address, kind) in [
+        (CHROMECAST, "chromecast"),
+        (APPLE_TV, "appletv"),
+        (PRINTER, "printer"),
+        (HOMEKIT, "homekit"),
+    ] {
+        let endpoint = endpoint(&endpoints, address);
+        assert_eq!(endpoint.fingerprint.as_ref().unwrap().kind, kind);
+        assert!(endpoint.online);
+    }
+}
+
+#[test]
+fn goodbye() {
+    let endpoints = discover(vec![
+        packet(CHROMECAST, chromecast(CHROMECAST, TTL), T0),
+        packet(CHROMECAST, chromecast(CHROMECAST, 0), T0 + 10),
+    ]);
+    let endpoint = endpoint(&endpoints, CHROMECAST);
+
+    assert!(!endpoint.online);
+    assert!(endpoint.services.is_empty());
+    assert!(endpoint.instances.is_empty());
+    assert!(endpoint.candidates.is_empty());
+    assert!(endpoint.fingerprint.is_none());
+}
+
+#[test]
+fn enumeration_first() {
+    let (transport, peer) = MemoryTransport::pair();
+    let mut agent = Agent::builder()
+        .passive(true)
+        .transport(transport)
+        .build()
+        .unwrap();
+    let endpoints = agent.endpoints();
+    let events = agent.subscribe();
+    let handle = thread::spawn(move || agent.start());
+
+    // the first answer to an active scan only lists the service types
+    let enumeration = response(&[ptr(
+        "_services._dns-sd._udp.local",
+        "_googlecast._tcp.local",
+        TTL,
+    )]);
+    peer.send(packet(CHROMECAST, enumeration, T0));
+    peer.send(packet(CHROMECAST, chromecast(CHROMECAST, TTL), T0 + 1));
+    drop(peer);
+    handle.join().unwrap();
+
+    let discovered = events
+        .iter()
+        .filter(|event| matches!(event.event, Event::EndpointDiscovered { .. }))
+        .count();
+    assert_eq!(discovered, 1);
+
+    let endpoints = endpoints.lock().unwrap();
+    assert!(endpoint(&endpoints, CHROMECAST).online);
+}
+
+#[test]
+fn expiry_follows_packet_time() {
+    let endpoints = discover(vec![
+        packet(CHROMECAST, chromecast(CHROMECAST, TTL), T0),
+        packet(PRINTER, printer(PRINTER, 4500), T0),
+        // only the chromecast records are expired by now
+        packet(
+            LAPTOP,
+            query(&[("_ipp._tcp.local", PTR, false)], &[]),
+            T0 + 600,
+        ),
+    ]);
+
+    assert!(!endpoint(&endpoints, CHROMECAST).online);
+    assert!(endpoint(&endpoints, CHROMECAST).fingerprint.is_none());
+    assert!(endpoint(&endpoints, PRINTER).online);
+}
+
+#[test]
+fn expired_services_are_not_fingerprinted() {
+    // a device announcing a short lived cast service next to its printer
+    let endpoints = discover(vec![
+        packet(CHROMECAST, chromecast(CHROMECAST, TTL), T0),
+        packet(CHROMECAST, printer(CHROMECAST, 4500), T0),
+        packet(
+            LAPTOP,
+            query(&[("_ipp._tcp.local", PTR, false)], &[]),
+            T0 + 600,
+        ),
+    ]);
+    let endpoint = endpoint(&endpoints, CHROMECAST);
+
+    assert!(endpoint.online);
+    assert!(endpoint
+        .candidates
+        .iter()
+        .all(|candidate| candidate.kind != "chromecast"));
+    assert_eq!(endpoint.fingerprint.as_ref().unwrap().kind, "printer");
+}
+
+#[test]
+fn queries_are_interests() {
+    let known_answer = ptr("_airplay._tcp.local", "Bedroom._airplay._tcp.local", 4500);
+    let asking = query(
+        &[
+            ("_airplay._tcp.local", PTR, true),
+            ("_ipp._tcp.local", PTR, false),
+        ],
+        &[known_answer],
+    );
+
+    let endpoints = discover(vec![
+        packet(LAPTOP, asking.clone(), T0),
+        packet(LAPTOP, asking, T0 + 5),
+    ]);
+    let endpoint = endpoint(&endpoints, LAPTOP);
+    assert!(endpoint.services.is_empty());
+    assert_eq!(endpoint.interests.len(), 2);
+
+    let airplay = endpoint
+        .interests
+        .iter()
+        .find(|interest| interest.name == "_airplay._tcp.local")
+        .unwrap();
+    assert_eq!(airplay.query_type, "PTR");
+    assert!(airplay.unicast_response);
+    assert_eq!(airplay.count, 2);
+    assert_eq!(airplay.first_seen, T0);
+    assert_eq!(airplay.last_seen, T0 + 5);
+    assert_eq!(airplay.known_answers, vec!["Bedroom._airplay._tcp.local"]);
+}
+
+#[test]
+fn filter_for() {
+    let endpoints = discover_with(
+        Agent::builder()
+            .passive(true)
+            .filter_for(Some(PRINTER.to_string())),
+        vec![
+            packet(CHROMECAST, chromecast(CHROMECAST, TTL), T0),
+            packet(PRINTER, printer(PRINTER, TTL), T0),
+        ],
+    );
+
+    assert_eq!(endpoints.len(), 1);
+    endpoint(&endpoints, PRINTER);
+}
+
+#[test]
+fn active_mode_follows_services() {
+    let (transport, peer) = MemoryTransport::pair();
+    let mut agent = Agent::builder()
+        .query_interval(60)
+        .transport(transport)
+        .build()
+        .unwrap();
+    let endpoints = agent.endpoints();
+    let handle = thread::spawn(move || agent.start());
+
+    let timeout = Duration::from_secs(5);
+    let group = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353);
+
+    // services enumeration first
+    let (destination, data) = peer.recv_timeout(timeout).unwrap();
+    assert_eq!(destination, group);
+    let sent = dns_parser::Packet::parse(&data).unwrap();
+    assert!(sent.header.query);
+    assert_eq!(
+        sent.questions[0].qname.to_string(),
+        "_services._dns-sd._udp.local"
+    );
+
+    // then the instances of the services that have been announced
+    let services = response(&[ptr(
+        "_services._dns-sd._udp.local",
+        "_googlecast._tcp.local",
+        4500,
+    )]);
+    assert!(peer.send(Packet::new(
+        SocketAddr::new(CHROMECAST.into(), 5353),
+        services
+    )));
+
+    let (_, data) = peer.recv_timeout(timeout).unwrap();
+    let sent = dns_parser::Packet::parse(&data).unwrap();
+    let questions: Vec<String> = sent
+        .questions
+        .iter()
+        .map(|question| question.qname.to_string())
+        .collect();
+    assert!(questions.contains(&"_googlecast._tcp.local".to_string()));
+
+    assert!(peer.send(Packet::new(
+        SocketAddr::new(CHROMECAST.into(), 5353),
+        chromecast(CHROMECAST, TTL)
+    )));
+    drop(peer);
+    handle.join().unwrap();
+
+    let endpoints = endpoints.lock().unwrap();
+    let fingerprint = endpoints[&IpAddr::V4(CHROMECAST)].fingerprint.as_ref();
+    assert_eq!(fingerprint.unwrap().kind, "chromecast");
+}
+
+#[test]
+fn active_mode_forgets_what_is_gone() {
+    let (transport, peer) = MemoryTransport::pair();
+    let mut agent = Agent::builder()
+        .query_interval(1)
+        .transport(transport)
+        .build()
+        .unwrap();
+    let stop = agent.stop_handle();
+    let handle = thread::spawn(move || agent.start());
+
+    let timeout = Duration::from_secs(5);
+    let source = SocketAddr::new(CHROMECAST.into(), 5353);
+    let questions = |data: Vec<u8>| -> Vec<String> {
+        let sent = dns_parser::Packet::parse(&data).unwrap();
+        sent.questions
+            .iter()
+            .map(|question| question.qname.to_string())
+            .collect()
+    };
+    let instance = "Chromecast-Ultra-4f2a._googlecast._tcp.local".to_string();
+    let enumeration = "_services._dns-sd._udp.local".to_string();
+
+    peer.recv_timeout(timeout).unwrap();
+    let mut records = vec![ptr(&enumeration, "_googlecast._tcp.local", TTL)];
+    records.extend(announcement(
+        "_googlecast._tcp",
+        "Chromecast-Ultra-4f2a",
+        "4f2a",
+        CHROMECAST,
+        8009,
+        &["md=Chromecast Ultra"],
+        TTL,
+    ));
+    assert!(peer.send(Packet::new(source, response(&records))));
+
+    // the instance and its host are resolved
+    let (_, data) = peer.recv_timeout(timeout).unwrap();
+    let asked = questions(data);
+    assert!(asked.contains(&instance));
+    assert!(asked.contains(&"4f2a.local".to_string()));
+
+    // until the device says goodbye
+    assert!(peer.send(Packet::new(source, chromecast(CHROMECAST, 0))));
+
+    let mut enumerations = 0;
+    let deadline = std::time::Instant::now() + Duration::from_millis(3500);
+    while let Some(left) = deadline.checked_duration_since(std::time::Instant::now()) {
+        let data = match peer.recv_timeout(left) {
+            Some((_, data)) => data,
+            None => break,
+        };
+        let asked = questions(data);
+        assert!(!asked.contains(&instance));
+        assert!(!asked.contains(&"4f2a.local".to_string()));
+        if asked.contains(&enumeration) {
+            enumerations += 1;
+        }
+    }
+    // resolved names are not asked at every interval
+    assert!(enumerations <= 2);
+
+    stop.stop();
+    drop(peer);
+    handle.join().unwrap();
+}
+
+#[test]
+fn pcap_replay() {
+    let path = temp_path("replay.pcapng");
+    let path = path.to_str().unwrap().to_owned();
+
+    let group = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353);
+    let mut writer = pcap::Writer::create(&path).unwrap();
+    let id = writer.add_interface("eth0", "test capture", None).unwrap();
+    for (address, data) in [
+        (CHROMECAST, chromecast(CHROMECAST, TTL)),
+        (HOMEKIT, homekit(HOMEKIT, TTL)),
+    ] {
+        let source = SocketAddr::new(address.into(), 5353);
+        writer
+            .write(id, pcap::Direction::Inbound, &source, &group, &data)
+            .unwrap();
+    }
+    drop(writer);
+
+    let mut agent = Agent::builder().pcap(&path).build().unwrap();
+    let endpoints = agent.endpoints();
+    // returns once the whole capture has been read
+    agent.start();
+    std::fs::remove_file(&path).unwrap();
+
+    let endpoints = endpoints.lock().unwrap();
+    assert_eq!(endpoints.len(), 2);
+    let endpoint = endpoint(&endpoints, HOMEKIT);
+    assert_eq!(endpoint.interface.as_deref(), Some("eth0"));
+    assert_eq!(endpoint.fingerprint.as_ref().unwrap().kind, "homekit");
+}
+
+#[test]
+fn unicast_response_sets_qu_bit() {
+    let (transport, peer) = MemoryTransport::pair();
+    let mut agent = Agent::builder()
+        .unicast_response(true)
+        .transport(transport)
+        .build()
+        .unwrap();
+    let handle = thread::spawn(move || agent.start());
+
+    let (_, data) = peer.recv_timeout(Duration::from_secs(5)).unwrap();
+    let sent = dns_parser::Packet::parse(&data).unwrap();
+    assert!(sent
+        .questions
+        .iter()
+        .all(|question| question.prefer_unicast));
+
+    drop(peer);
+    handle.join().unwrap();
+}
+
+#[test]
+fn unicast_target() {
+    // a responder that's only reachable by unicast
+    let responder = UdpSocket::bind("127.0.0.1:0").unwrap();
+    responder
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let target = responder.local_addr().unwrap();
+    let localhost = Ipv4Addr::LOCALHOST;
+
+    let mut agent = Agent::builder()
+        .ipv6(false)
+        .query_interval(60)
+        .target(target)
+        .build()
+        .unwrap();
+    let endpoints = agent.endpoints();
+    let stop = agent.stop_handle();
+    let handle = thread::spawn(move || agent.start());
+
+    let mut buffer = [0; 4096];
+    let (count, source) = responder.recv_from(&mut buffer).unwrap();
+    // legacy unicast queries don't come from the mDNS port
+    assert_ne!(source.port(), 5353);
+    let sent = dns_parser::Packet::parse(&buffer[..count]).unwrap();
+    assert_eq!(
+        sent.questions[0].qname.to_string(),
+        "_services._dns-sd._udp.local"
+    );
+
+    let mut records = vec![ptr(
+        "_services._dns-sd._udp.local",
+        "_googlecast._tcp.local",
+        TTL,
+    )];
+    records.extend(announcement(
+        "_googlecast._tcp",
+        "Chromecast-Ultra-4f2a",
+        "4f2a",
+        localhost,
+        8009,
+        &["md=Chromecast Ultra"],
+        TTL,
+    ));
+    responder.send_to(&response(&records), source).unwrap();
+
+    // the instance is resolved next
+    let (count, _) = responder.recv_from(&mut buffer).unwrap();
+    let sent = dns_parser::Packet::parse(&buffer[..count]).unwrap();
+    assert!(sent.questions.iter().any(
+        |question| question.qname.to_string() == "Chromecast-Ultra-4f2a._googlecast._tcp.local"
+    ));
+
+    stop.stop();
+    handle.join().unwrap();
+
+    let endpoints = endpoints.lock().unwrap();
+    let endpoint = endpoint(&endpoints, localhost);
+    assert_eq!(endpoint.fingerprint.as_ref().unwrap().kind, "chromecast");
+}
+
+#[test]
+fn unicast_target_options() {
+    let target: SocketAddr = "127.0.0.1:5353".parse().unwrap();
+
+    // targets are not queried on the interfaces
+    let interface = Agent::builder().interface("lo").target(target).build();
+    assert!(interface.is_err());
+    // nor over a disabled IP version
+    let disabled = Agent::builder().ipv4(false).target(target).build();
+    assert!(disabled.is_err());
+}
+
+#[test]
+fn recorded_packets_have_their_destination() {
+    let path = temp_path("record.pcapng");
+    let path = path.to_str().unwrap().to_owned();
+
+    let group = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353);
+    let unicast = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2)), 5353);
+    let mut direct = packet(PRINTER, printer(PRINTER, TTL), T0);
+    direct.destination = Some(unicast);
+
+    discover_with(
+        Agent::builder().passive(true).write_pcap(&path),
+        vec![packet(CHROMECAST, chromecast(CHROMECAST, TTL), T0), direct],
+    );
+
+    let captured: Vec<pcap::Captured> = pcap::Reader::open(&path)
+        .unwrap()
+        .map(|captured| captured.unwrap())
+        .collect();
+    std::fs::remove_file(&path).unwrap();
+
+    // recorded once each, to the group unless the transport knows better
+    assert_eq!(captured.len(), 2);
+    assert_eq!(captured[0].source.ip(), IpAddr::V4(CHROMECAST));
+    assert_eq!(captured[0].destination, group);
+    assert_eq!(captured[1].source.ip(), IpAddr::V4(PRINTER));
+    assert_eq!(captured[1].destination, unicast);
+}
+
+#[test]
+fn udp_packets_have_their_destination() {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let address = socket.local_addr().unwrap();
+    let mut transport = UdpTransport::new(socket);
+
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    sender.send_to(b"hello", address).unwrap();
+
+    let packet = transport.recv(Duration::from_secs(5)).unwrap().unwrap();
+    assert_eq!(packet.source, sender.local_addr().unwrap());
+    assert_eq!(packet.data, b"hello");
+    if cfg!(any(target_os = "linux", target_os = "macos")) {
+        assert_eq!(packet.destination, Some(address));
+    }
+}
+
+#[test]
+fn restored_endpoints_are_stale() {
+    let previous = discover(vec![
+        packet(CHROMECAST, chromecast(CHROMECAST, TTL), T0),
+        packet(PRINTER, printer(PRINTER, TTL), T0),
+    ]);
+
+    // long after the records of the previous scan expired
+    let endpoints = discover_with(
+        Agent::builder().passive(true).restore(previous),
+        vec![
+            packet(PRINTER, printer(PRINTER, TTL), T0 + 3600),
+            packet(
+                LAPTOP,
+                query(&[("_ipp._tcp.local", PTR, false)], &[]),
+                T0 + 3601,
+            ),
+        ],
+    );
+    assert_eq!(endpoints.len(), 3);
+
+    let chromecast = endpoint(&endpoints, CHROMECAST);
+    assert!(chromecast.stale);
+    assert!(chromecast.online);
+    assert_eq!(chromecast.instances.len(), 1);
+    assert_eq!(chromecast.fingerprint.as_ref().unwrap().kind, "chromecast");
+
+    let printer = endpoint(&endpoints, PRINTER);
+    assert!(!printer.stale);
+    assert!(printer.online);
+    assert_eq!(printer.last_seen, T0 + 3600);
+    assert_eq!(printer.first_seen, T0);
+
+    assert!(!endpoint(&endpoints, LAPTOP).stale);
+}
+
+#[test]
+fn restored_endpoints_are_back_when_asking() {
+    let previous = discover(vec![packet(CHROMECAST, chromecast(CHROMECAST, TTL), T0)]);
+
+    let (transport, peer) = MemoryTransport::pair();
+    let mut agent = Agent::builder()
+        .passive(true)
+        .restore(previous)
+        .transport(transport)
+        .build()
+        .unwrap();
+    let endpoints = agent.endpoints();
+    let events = agent.subscribe();
+    let handle = thread::spawn(move || agent.start());
+
+    let asking = query(&[("_googlecast._tcp.local", PTR, false)], &[]);
+    peer.send(packet(CHROMECAST, asking, T0 + 3600));
+    drop(peer);
+    handle.join().unwrap();
+
+    // reported as it would be if it was answering
+    let discovered = events
+        .iter()
+        .filter(|event| matches!(event.event, Event::EndpointDiscovered { .. }))
+        .count();
+    assert_eq!(discovered, 1);
+
+    let endpoints = endpoints.lock().unwrap();
+    assert!(!endpoint(&endpoints, CHROMECAST).stale);
+}
+
+#[test]
+fn events_serialize_to_json() {
+    let (transport, peer) = MemoryTransport::pair();
+    let mut agent = Agent::builder()
+        .passive(true)
+        .transport(transport)
+        .build()
+        .unwrap();
+    let events = agent.subscribe();
+    let handle = thread::spawn(move || agent.start());
+
+    peer.send(packet(CHROMECAST, chromecast(CHROMECAST, TTL), T0));
+    peer.send(packet(CHROMECAST, chromecast(CHROMECAST, 0), T0 + 1));
+    drop(peer);
+    handle.join().unwrap();
+
+    let events: Vec<serde_json::Value> = events
+        .iter()
+        .map(|event| serde_json::to_value(&event).unwrap())
+        .collect();
+
+    // at the time of the packet that caused them
+    let discovered = &events[0];
+    assert_eq!(discovered["timestamp"], T0);
+    assert_eq!(discovered["event"], "endpoint_discovered");
+    assert_eq!(discovered["endpoint"]["address"], "192.168.1.20");
+    assert_eq!(discovered["endpoint"]["fingerprint"]["kind"], "chromecast");
+
+    let expired = &events[1];
+    assert_eq!(expired["timestamp"], T0 + 1);
+    assert_eq!(expired["event"], "record_expired");
+    assert_eq!(expired["address"], "192.168.1.20");
+    assert!(expired["service"].is_string());
+
+    assert_eq!(events.last().unwrap()["event"], "endpoint_gone");
+}
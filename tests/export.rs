@@ -0,0 +1,115 @@
+use spycast::export::{self, Column};
+
+mod common;
+use common::*;
+
+fn export_csv(columns: &[&str]) -> Vec<Vec<String>> {
+    let mut packets = two_devices();
+    packets.push(packet(
+        LAPTOP,
+        query(&[("_ipp._tcp.local", PTR, false)], &[]),
+        T0 + 1,
+    ));
+    let endpoints = discover(packets);
+
+    let columns = export::columns(columns).unwrap();
+    let mut out = vec![];
+    export::write_csv(&mut out, &endpoints, &columns).unwrap();
+
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(out.as_slice())
+        .records()
+        .map(|record| record.unwrap().iter().map(String::from).collect())
+        .collect()
+}
+
+#[test]
+fn export_default_columns() {
+    let columns: Vec<&str> = export::DEFAULT_COLUMNS.split(',').collect();
+    let rows = export_csv(&columns);
+
+    assert_eq!(rows[0], columns);
+    // one row per instance, sorted by address, and one for the endpoint without instances
+    assert_eq!(rows.len(), 4);
+    assert_eq!(
+        rows[1],
+        vec![
+            "192.168.1.20",
+            "4f2a.local",
+            "google",
+            "chromecast",
+            "Chromecast Ultra",
+            "_googlecast._tcp.local",
+            "Google Cast (Chromecast)",
+            "Chromecast-Ultra-4f2a._googlecast._tcp.local",
+            "8009",
+            "fn=Living Room TV;id=4f2a;md=Chromecast Ultra;ve=05",
+        ]
+    );
+    assert_eq!(rows[2][0], "192.168.1.22");
+    assert_eq!(rows[2][5], "_ipp._tcp.local");
+    assert_eq!(rows[2][8], "631");
+
+    // only the endpoint columns are filled
+    assert_eq!(rows[3][0], "192.168.1.30");
+    assert!(rows[3][5..].iter().all(|value| value.is_empty()));
+}
+
+#[test]
+fn export_selected_columns() {
+    let rows = export_csv(&["address", "port", "txt.TY", "txt.md"]);
+
+    assert_eq!(rows[0], vec!["address", "port", "txt.ty", "txt.md"]);
+    assert_eq!(
+        rows[1],
+        vec!["192.168.1.20", "8009", "", "Chromecast Ultra"]
+    );
+    assert_eq!(
+        rows[2],
+        vec!["192.168.1.22", "631", "HP LaserJet Pro M404dn", ""]
+    );
+}
+
+#[test]
+fn export_services_without_instances() {
+    // SRV and TXT records that no PTR points to yet
+    let spotify = "Kitchen._spotify-connect._tcp.local";
+    let endpoints = discover(vec![
+        packet(CHROMECAST, chromecast(CHROMECAST, TTL), T0),
+        packet(
+            CHROMECAST,
+            response(&[
+                srv(spotify, 4070, "4f2a.local", TTL),
+                txt(spotify, &["cpath=/zc"], TTL),
+            ]),
+            T0 + 1,
+        ),
+    ]);
+
+    let columns = export::columns(&["service", "description", "instance", "port", "txt"]).unwrap();
+    let rows = export::rows(&endpoints, &columns);
+
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0][0], "_googlecast._tcp.local");
+    assert_eq!(
+        rows[1],
+        vec![
+            "_spotify-connect._tcp.local",
+            "Spotify Connect",
+            spotify,
+            "4070",
+            "cpath=/zc"
+        ]
+    );
+}
+
+#[test]
+fn export_unknown_columns() {
+    assert_eq!(Column::parse("port"), Ok(Column::Port));
+    assert!(Column::parse("mac")
+        .unwrap_err()
+        .contains("unknown column 'mac'"));
+    assert!(Column::parse("txt.").is_err());
+    assert!(export::columns::<&str>(&[]).is_err());
+}
@@ -0,0 +1,162 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use spycast::{snapshot, Endpoint, Event, SharedEndpoints, Timestamped};
+
+mod common;
+use common::*;
+
+fn endpoints() -> SharedEndpoints {
+    Arc::new(Mutex::new(discover(two_devices())))
+}
+
+fn gone(address: Ipv4Addr) -> Timestamped {
+    Timestamped {
+        timestamp: T0,
+        event: Event::EndpointGone {
+            address: IpAddr::V4(address),
+        },
+    }
+}
+
+fn files(path: &Path) -> Vec<String> {
+    let mut files: Vec<String> = std::fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    files.sort();
+    files
+}
+
+#[test]
+fn filenames_are_portable() {
+    let address = IpAddr::V6(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1));
+    assert_eq!(snapshot::filename(&address), "fe80__1.json");
+    assert_eq!(
+        snapshot::filename(&IpAddr::V4(CHROMECAST)),
+        "192.168.1.20.json"
+    );
+}
+
+#[test]
+fn saves_only_what_changed() {
+    let path = temp_path("changed");
+    let writer =
+        snapshot::Writer::new(path.to_str().unwrap(), Duration::from_secs(60), endpoints());
+
+    let (tx, rx) = mpsc::channel();
+    let handle = thread::spawn(move || writer.run(rx));
+    tx.send(gone(PRINTER)).unwrap();
+    tx.send(gone(PRINTER)).unwrap();
+    // debounced, nothing is written before the interval
+    thread::sleep(Duration::from_millis(100));
+    assert!(files(&path).is_empty());
+
+    // whatever is left is written once the events stop
+    drop(tx);
+    handle.join().unwrap();
+    assert_eq!(files(&path), vec!["192.168.1.22.json"]);
+
+    let restored = snapshot::load(path.to_str().unwrap());
+    let endpoint: &Endpoint = &restored[&IpAddr::V4(PRINTER)];
+    assert_eq!(endpoint.fingerprint.as_ref().unwrap().kind, "printer");
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn writes_at_every_interval() {
+    let path = temp_path("interval");
+    let writer = snapshot::Writer::new(
+        path.to_str().unwrap(),
+        Duration::from_millis(50),
+        endpoints(),
+    );
+
+    let (tx, rx) = mpsc::channel();
+    let handle = thread::spawn(move || writer.run(rx));
+    tx.send(gone(CHROMECAST)).unwrap();
+    thread::sleep(Duration::from_millis(500));
+    // still running
+    assert_eq!(files(&path), vec!["192.168.1.20.json"]);
+
+    tx.send(gone(PRINTER)).unwrap();
+    drop(tx);
+    handle.join().unwrap();
+    assert_eq!(files(&path), vec!["192.168.1.20.json", "192.168.1.22.json"]);
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn loads_older_snapshots() {
+    // as saved before the records had their metadata
+    let path = temp_path("older");
+    std::fs::create_dir_all(&path).unwrap();
+    std::fs::write(
+        path.join("192.168.1.20.json"),
+        r#"{
+  "name": "4f2a.local",
+  "address": "192.168.1.20",
+  "local": false,
+  "services": {
+    "_googlecast._tcp.local": {
+      "name": "_googlecast._tcp.local",
+      "description": "Google Cast",
+      "properties": {
+        "name": ["Chromecast-Ultra-4f2a._googlecast._tcp.local"]
+      }
+    }
+  },
+  "fingerprint": { "vendor": "google", "kind": "chromecast" }
+}"#,
+    )
+    .unwrap();
+
+    let restored = snapshot::load(path.to_str().unwrap());
+    let endpoint: &Endpoint = &restored[&IpAddr::V4(CHROMECAST)];
+    assert_eq!(endpoint.name.as_deref(), Some("4f2a.local"));
+    assert_eq!(endpoint.fingerprint.as_ref().unwrap().kind, "chromecast");
+
+    let values = endpoint.services["_googlecast._tcp.local"]
+        .properties
+        .get("name")
+        .unwrap();
+    assert_eq!(
+        values[0].value,
+        "Chromecast-Ultra-4f2a._googlecast._tcp.local"
+    );
+
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn errors_are_not_fatal() {
+    // can't create a folder where a file is
+    let file = temp_path("not-a-folder");
+    std::fs::write(&file, "").unwrap();
+    let path = file.join("results");
+
+    let writer = snapshot::Writer::new(
+        path.to_str().unwrap(),
+        Duration::from_millis(10),
+        endpoints(),
+    );
+
+    let (tx, rx) = mpsc::channel();
+    let handle = thread::spawn(move || writer.run(rx));
+    tx.send(gone(CHROMECAST)).unwrap();
+    thread::sleep(Duration::from_millis(100));
+    drop(tx);
+    handle.join().unwrap();
+
+    assert!(snapshot::load(path.to_str().unwrap()).is_empty());
+    std::fs::remove_file(&file).unwrap();
+}
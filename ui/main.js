@@ -10,6 +10,32 @@ function escape(unsafe) {
 	return unsafe.replaceAll('&', '&amp;').replaceAll('<', '&lt;').replaceAll('>', '&gt;').replaceAll('"', '&quot;').replaceAll("'", '&#039;');
 }
 
+function formatTime(timestamp) {
+	return new Date(timestamp * 1000).toLocaleString();
+}
+
+function formatTxtValue(value) {
+	if (typeof value === 'boolean') {
+		return value ? 'true' : 'false';
+	} else if (typeof value === 'object') {
+		return `0x${value.hex}`;
+	}
+	return value;
+}
+
+function buildTxt(txt) {
+	var html = '';
+
+	for (var key of Object.keys(txt).sort()) {
+		html += `<li>
+					&nbsp;&nbsp;&nbsp; <span class="badge rounded-pill text-bg-light">${escape(key)}</span>
+					<code>${escape(formatTxtValue(txt[key]))}</code>
+				 </li>`;
+	}
+
+	return html;
+}
+
 function buildEndpointInfo(endpoint) {
 	var html = '<div class="info-header">';
 
@@ -29,13 +55,31 @@ function buildEndpointInfo(endpoint) {
 		html += `<span class="badge text-bg-warning">local</span> `;
 	}
 
+	if (endpoint.interface) {
+		html += `<span class="badge text-bg-dark">${escape(endpoint.interface)}</span> `;
+	}
+
+	if (!endpoint.online) {
+		html += `<span class="badge text-bg-danger">offline</span> `;
+	}
+
+	if (endpoint.stale) {
+		html += `<span class="badge text-bg-secondary">stale</span> `;
+	}
+
+	html += `<small class="text-muted">
+				first seen ${escape(formatTime(endpoint.first_seen))},
+				last seen ${escape(formatTime(endpoint.last_seen))}
+			</small>`;
+
 	if (endpoint.fingerprint != null) {
 		if (endpoint.fingerprint.vendor) {
 			html += `<span class="badge text-bg-primary">${escape(endpoint.fingerprint.vendor)}</span> `;
 		}
 		if (endpoint.fingerprint.kind) {
-			html += `<span class="badge text-bg-secondary">${escape(endpoint.fingerprint.kind)}</span>`;
+			html += `<span class="badge text-bg-secondary">${escape(endpoint.fingerprint.kind)}</span> `;
 		}
+		html += buildFingerprintDetails(endpoint.fingerprint);
 	}
 
 	html += '<hr/>';
@@ -60,7 +104,12 @@ function buildEndpointInfo(endpoint) {
 		html += '<ul class="list-unstyled">';
 
 		for (var pname in svc.properties) {
-			var values = svc.properties[pname];
+			// shown parsed
+			if ((pname == 'text' && Object.keys(svc.txt).length > 0) || (pname == 'server' && svc.srv.length > 0)) {
+				continue;
+			}
+
+			var values = svc.properties[pname].map((v) => v.value);
 			var badge = 'text-bg-secondary';
 
 			if (pname == 'ipv4' || pname == 'ipv6') {
@@ -77,6 +126,96 @@ function buildEndpointInfo(endpoint) {
 							 </li>`;
 		}
 
+		for (var j = 0; j < svc.srv.length; j++) {
+			var srv = svc.srv[j];
+
+			html += `<li>
+						&nbsp;&nbsp;&nbsp; <span class="badge rounded-pill text-bg-warning">server</span>
+						<code>${escape(srv.target)} port ${srv.port} (priority ${srv.priority}, weight ${srv.weight})</code>`;
+
+			if (srv.addresses.length > 0) {
+				html += ` at <code>${escape(srv.addresses.join(', '))}</code>`;
+			}
+
+			html += '</li>';
+		}
+
+		html += buildTxt(svc.txt);
+		html += '</ul>';
+	}
+
+	var instances = Object.values(endpoint.instances);
+	if (instances.length > 0) {
+		html += '<h4>Instances</h4>';
+		html += '<ul class="list-unstyled">';
+
+		for (var i = 0; i < instances.length; i++) {
+			var instance = instances[i];
+
+			html += `<li><b>${escape(instance.name)}</b>
+						<span class="blockquote-footer">${escape(instance.service_type)}</span>`;
+
+			if (instance.host) {
+				html += ` <span class="badge rounded-pill text-bg-warning">${escape(instance.host)}:${instance.port}</span>`;
+			}
+
+			if (instance.addresses.length > 0) {
+				html += ` <code>${escape(instance.addresses.join(', '))}</code>`;
+			}
+
+			html += '</li>';
+			html += buildTxt(instance.txt);
+		}
+
+		html += '</ul>';
+	}
+
+	if (endpoint.interests && endpoint.interests.length > 0) {
+		html += '<h4>Interests</h4>';
+		html += '<ul class="list-unstyled">';
+
+		for (var i = 0; i < endpoint.interests.length; i++) {
+			var interest = endpoint.interests[i];
+
+			html += `<li><b>${escape(interest.name)}</b>
+						<span class="badge rounded-pill text-bg-light">${escape(interest.query_type)}</span>`;
+			if (interest.unicast_response) {
+				html += ' <span class="badge rounded-pill text-bg-warning">QU</span>';
+			}
+			html += ` <small class="text-muted">
+						asked ${interest.count} times, last ${escape(formatTime(interest.last_seen))}
+					</small>`;
+
+			if (interest.known_answers.length > 0) {
+				html += '<ul>';
+				for (var j = 0; j < interest.known_answers.length; j++) {
+					html += `<li><code>${escape(interest.known_answers[j])}</code></li>`;
+				}
+				html += '</ul>';
+			}
+			html += '</li>';
+		}
+
+		html += '</ul>';
+	}
+
+	if (endpoint.candidates && endpoint.candidates.length > 0) {
+		html += '<h4>Fingerprints</h4>';
+		html += '<ul class="list-unstyled">';
+
+		for (var i = 0; i < endpoint.candidates.length; i++) {
+			var candidate = endpoint.candidates[i];
+			var label = [candidate.vendor, candidate.kind].filter(v => v).join(' ');
+
+			html += `<li><b>${escape(label)}</b>
+						<span class="badge rounded-pill text-bg-info">${Math.round(candidate.confidence * 100)}%</span>
+						<ul>`;
+			for (var j = 0; j < candidate.evidence.length; j++) {
+				html += `<li><small class="text-muted">${escape(candidate.evidence[j])}</small></li>`;
+			}
+			html += '</ul></li>';
+		}
+
 		html += '</ul>';
 	}
 
@@ -85,6 +224,31 @@ function buildEndpointInfo(endpoint) {
 	return html;
 }
 
+function buildFingerprintDetails(fingerprint) {
+	var details = [];
+	if (fingerprint.friendly_name) {
+		details.push(`"${fingerprint.friendly_name}"`);
+	}
+	if (fingerprint.model) {
+		details.push(fingerprint.model);
+	}
+	if (fingerprint.os || fingerprint.os_version) {
+		details.push([fingerprint.os, fingerprint.os_version].filter(v => v).join(' '));
+	}
+	if (fingerprint.firmware) {
+		details.push(`firmware ${fingerprint.firmware}`);
+	}
+	if (fingerprint.confidence) {
+		details.push(`${Math.round(fingerprint.confidence * 100)}% confidence`);
+	}
+
+	if (details.length == 0) {
+		return '';
+	}
+
+	return `<small class="text-muted">${escape(details.join(', '))}</small>`;
+}
+
 function endpointIcon(endpoint) {
 	if (endpoint.local) {
 		return '<i class="bi bi-ethernet"></i>';
@@ -99,8 +263,16 @@ function endpointIcon(endpoint) {
 			return '<i class="bi bi-hdd-network"></i>';
 		} else if (kind == 'light') {
 			return '<i class="bi bi-lightbulb"></i>';
-		} else if (kind == 'osx' || kind == 'apple') {
+		} else if (kind == 'osx' || kind == 'apple' || kind == 'appletv') {
 			return '<i class="bi bi-apple"></i>';
+		} else if (kind == 'speaker') {
+			return '<i class="bi bi-speaker"></i>';
+		} else if (kind == 'firetv') {
+			return '<i class="bi bi-tv"></i>';
+		} else if (kind == 'homekit') {
+			return '<i class="bi bi-house"></i>';
+		} else if (kind == 'printer') {
+			return '<i class="bi bi-printer"></i>';
 		}
 	}
 